@@ -1,16 +1,46 @@
-use crate::notes::{Note, NotesState, WindowLevel};
-use crate::storage::{media_dir, save_notes};
-use crate::window::open_note_window;
+use crate::diff::{self, NoteDiff};
+use crate::notes::{Note, NoteLock, NotesState, NotesStore, WindowLevel, LOCKED_PLACEHOLDER};
+use crate::protocol;
+use crate::settings::{self, Settings, StorageBackend};
+use crate::storage::archive::{self, ExportSummary, ImportMode, ImportSummary};
+use crate::storage::attachment::{self, Attachment, AttachmentError};
+use crate::storage::backup::{self, BackupInfo, BackupKind};
+use crate::storage::history::{self, NoteVersion, VersionInfo};
+use crate::storage::image::ImageError;
+use crate::storage::import::{self, ImportError, Imported};
+use crate::storage::{self, crypto, DataDirInfo, RecoveryReport};
+use crate::tray;
+use crate::watcher;
+use crate::window::{open_note_window, open_note_window_centered, UNLOCK_WINDOW};
 use serde::Serialize;
+use tauri::ipc::InvokeBody;
 use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_dialog::{
+    DialogExt, MessageDialogButtons, MessageDialogKind, MessageDialogResult,
+};
+use tauri_plugin_opener::OpenerExt;
 
 use base64::Engine;
-use std::fs;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+const MAINTENANCE_DELAY: Duration = Duration::from_secs(10 * 60);
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const BACKUP_DELAY: Duration = Duration::from_secs(2 * 60);
+const BACKUP_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Held while `unlock_store` runs, so the store is only loaded once.
+static UNLOCKING: Mutex<()> = Mutex::new(());
 
 #[derive(Clone, Serialize)]
 struct NotesChangedPayload {
     kind: &'static str,
     id: String,
+    /// True when the change came from outside the app, e.g. a sync tool
+    /// rewriting notes.json, so open editors know to reload.
+    external: bool,
 }
 
 fn emit_notes_changed(app: &AppHandle, kind: &'static str, id: &str) {
@@ -19,19 +49,63 @@ fn emit_notes_changed(app: &AppHandle, kind: &'static str, id: &str) {
         NotesChangedPayload {
             kind,
             id: id.to_string(),
+            external: false,
+        },
+    );
+}
+
+pub fn emit_external_change(app: &AppHandle, kind: &'static str, id: &str) {
+    let _ = app.emit(
+        "notes-changed",
+        NotesChangedPayload {
+            kind,
+            id: id.to_string(),
+            external: true,
         },
     );
 }
 
+fn referenced_media(content: &str) -> Vec<String> {
+    storage::media_references(content)
+        .map(String::from)
+        .collect()
+}
+
+/// What the frontend gets to see of a note: a locked note's content is
+/// replaced with a placeholder unless it was unlocked this session.
+fn note_view(store: &NotesStore, note: &Note) -> Note {
+    let mut view = note.clone();
+    if let Some(lock) = view.lock.as_mut() {
+        let content = store
+            .unlocked_notes
+            .get(&note.id)
+            .map(|key| crypto::open_text_with(Some(key), &lock.content));
+        match content {
+            Some(Ok(content)) => {
+                view.content = content;
+                lock.unlocked = true;
+            }
+            Some(Err(e)) => {
+                eprintln!("failed to decrypt locked note {}: {}", note.id, e);
+                view.content = LOCKED_PLACEHOLDER.to_string();
+            }
+            None => view.content = LOCKED_PLACEHOLDER.to_string(),
+        }
+    }
+    view
+}
+
 #[tauri::command]
 pub fn create_note(app: AppHandle, state: State<'_, NotesState>) -> Result<Note, String> {
+    if crypto::is_locked() {
+        return Err("Notes are locked".to_string());
+    }
     let mut store = state.lock().map_err(|e| e.to_string())?;
     let note = Note::new();
     let note_clone = note.clone();
+    store.writer.put(&note);
     store.notes.insert(note.id.clone(), note);
-    let snapshot = store.notes.clone();
     drop(store);
-    save_notes(&snapshot);
     open_note_window(&app, &note_clone)?;
     emit_notes_changed(&app, "created", &note_clone.id);
     Ok(note_clone)
@@ -40,7 +114,11 @@ pub fn create_note(app: AppHandle, state: State<'_, NotesState>) -> Result<Note,
 #[tauri::command]
 pub fn list_notes(state: State<'_, NotesState>) -> Result<Vec<Note>, String> {
     let store = state.lock().map_err(|e| e.to_string())?;
-    let mut notes: Vec<Note> = store.notes.values().cloned().collect();
+    let mut notes: Vec<Note> = store
+        .notes
+        .values()
+        .map(|note| note_view(&store, note))
+        .collect();
     notes.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
     Ok(notes)
 }
@@ -51,33 +129,62 @@ pub fn get_note(id: String, state: State<'_, NotesState>) -> Result<Note, String
     store
         .notes
         .get(&id)
-        .cloned()
+        .map(|note| note_view(&store, note))
         .ok_or_else(|| format!("Note {} not found", id))
 }
 
 #[tauri::command]
-pub fn update_note(note: Note, app: AppHandle, state: State<'_, NotesState>) -> Result<(), String> {
+pub fn update_note(
+    mut note: Note,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<(), String> {
     let mut store = state.lock().map_err(|e| e.to_string())?;
-    if store.notes.contains_key(&note.id) {
+    if let Some(existing) = store.notes.get(&note.id) {
+        // Locks are only changed through the lock commands; whatever the
+        // frontend sent back is replaced with what is stored.
+        note.lock = existing.lock.clone();
+        if existing.lock.is_none() {
+            if let Err(e) = history::record_change(&note.id, &existing.content, &note.content) {
+                eprintln!("failed to record history for note {}: {}", note.id, e);
+            }
+        }
+        if let Some(lock) = note.lock.as_mut() {
+            match store.unlocked_notes.get(&note.id) {
+                Some(key) => {
+                    lock.content = crypto::seal_text_with(Some(key), &note.content)?;
+                    lock.media = referenced_media(&note.content);
+                }
+                None if note.content != LOCKED_PLACEHOLDER => {
+                    return Err("Note is locked".to_string());
+                }
+                None => {}
+            }
+            note.content = String::new();
+        }
         let note_id = note.id.clone();
+        store.writer.put(&note);
         store.notes.insert(note.id.clone(), note);
-        let snapshot = store.notes.clone();
         drop(store);
-        save_notes(&snapshot);
         emit_notes_changed(&app, "updated", &note_id);
         Ok(())
     } else {
-        Err(format!("Note not found"))
+        Err("Note not found".to_string())
     }
 }
 
+/// Moves a note to the trash. It can be brought back with `restore_note`
+/// until it is purged by `empty_trash` or the `trash_retention_days` setting.
 #[tauri::command]
 pub fn delete_note(id: String, app: AppHandle, state: State<'_, NotesState>) -> Result<(), String> {
     let mut store = state.lock().map_err(|e| e.to_string())?;
-    store.notes.remove(&id);
-    let snapshot = store.notes.clone();
+    if let Some(mut note) = store.notes.remove(&id) {
+        note.deleted_at = Some(chrono::Utc::now().to_rfc3339());
+        store.writer.put(&note);
+        store.unlocked_notes.remove(&id);
+        store.trash.insert(id.clone(), note);
+    }
     drop(store);
-    save_notes(&snapshot);
     if let Some(window) = app.get_webview_window(&id) {
         window.destroy().map_err(|e| e.to_string())?;
     }
@@ -85,6 +192,263 @@ pub fn delete_note(id: String, app: AppHandle, state: State<'_, NotesState>) ->
     Ok(())
 }
 
+/// Notes in the trash, most recently deleted first.
+#[tauri::command]
+pub fn list_trash(state: State<'_, NotesState>) -> Result<Vec<Note>, String> {
+    let store = state.lock().map_err(|e| e.to_string())?;
+    let mut notes: Vec<Note> = store
+        .trash
+        .values()
+        .map(|note| note_view(&store, note))
+        .collect();
+    notes.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(notes)
+}
+
+/// Takes a note out of the trash.
+#[tauri::command]
+pub fn restore_note(
+    id: String,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<Note, String> {
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    let mut note = store.trash.remove(&id).ok_or("Note not found in trash")?;
+    note.deleted_at = None;
+    store.writer.put(&note);
+    store.notes.insert(id.clone(), note.clone());
+    let view = note_view(&store, &note);
+    drop(store);
+    emit_notes_changed(&app, "created", &id);
+    Ok(view)
+}
+
+/// Permanently deletes everything in the trash. Returns how many notes were
+/// removed.
+#[tauri::command]
+pub fn empty_trash(app: AppHandle, state: State<'_, NotesState>) -> Result<usize, String> {
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    let ids: Vec<String> = store.trash.drain().map(|(id, _)| id).collect();
+    for id in &ids {
+        store.writer.delete(id);
+        history::remove(id);
+    }
+    drop(store);
+    for id in &ids {
+        emit_notes_changed(&app, "purged", id);
+    }
+    Ok(ids.len())
+}
+
+/// Permanently deletes notes that have been in the trash longer than the
+/// `trash_retention_days` setting and returns their ids.
+fn purge_expired_trash(store: &mut NotesStore) -> Vec<String> {
+    let days = settings::current().trash_retention_days;
+    if days == 0 {
+        return Vec::new();
+    }
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(days as i64);
+    let expired: Vec<String> = store
+        .trash
+        .values()
+        .filter(|note| {
+            note.deleted_at
+                .as_deref()
+                .and_then(|at| chrono::DateTime::parse_from_rfc3339(at).ok())
+                .is_some_and(|at| at < cutoff)
+        })
+        .map(|note| note.id.clone())
+        .collect();
+    for id in &expired {
+        store.writer.delete(id);
+        store.trash.remove(id);
+        history::remove(id);
+    }
+    expired
+}
+
+/// Locks a note with its own password. The note stays locked until
+/// `unlock_note` is called with the same password.
+#[tauri::command]
+pub fn lock_note(
+    id: String,
+    password: String,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<Note, String> {
+    let (key, kdf) = crypto::new_key(&password)?;
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    let note = store.notes.get_mut(&id).ok_or("Note not found")?;
+    if note.lock.is_some() {
+        return Err("Note is already locked".to_string());
+    }
+    note.lock = Some(NoteLock {
+        kdf,
+        content: crypto::seal_text_with(Some(&key), &note.content)?,
+        media: referenced_media(&note.content),
+        unlocked: false,
+    });
+    note.content = String::new();
+    let note = note.clone();
+    store.writer.put(&note);
+    // Earlier versions are plaintext and would give the content away, and
+    // so would older copies of the note wherever the store keeps them.
+    history::remove(&id);
+    if let Err(e) = store.writer.compact() {
+        eprintln!("failed to compact storage after locking note {}: {}", id, e);
+    }
+    if let Err(e) = storage::scrub_note(&note) {
+        eprintln!("failed to remove old copies of note {}: {}", id, e);
+    }
+    let view = note_view(&store, &note);
+    drop(store);
+    emit_notes_changed(&app, "updated", &id);
+    Ok(view)
+}
+
+/// Earlier versions of a note's content, newest first.
+#[tauri::command]
+pub fn list_note_versions(
+    id: String,
+    state: State<'_, NotesState>,
+) -> Result<Vec<VersionInfo>, String> {
+    let store = state.lock().map_err(|e| e.to_string())?;
+    if !store.notes.contains_key(&id) && !store.trash.contains_key(&id) {
+        return Err(format!("Note {} not found", id));
+    }
+    history::list(&id)
+}
+
+#[tauri::command]
+pub fn get_note_version(
+    id: String,
+    version_id: String,
+    state: State<'_, NotesState>,
+) -> Result<NoteVersion, String> {
+    let store = state.lock().map_err(|e| e.to_string())?;
+    if !store.notes.contains_key(&id) && !store.trash.contains_key(&id) {
+        return Err(format!("Note {} not found", id));
+    }
+    history::get(&id, &version_id)
+}
+
+/// What changed from version `from` to version `to` of a note, or to its
+/// current content when `to` is missing, compared as plain text.
+#[tauri::command]
+pub fn diff_note_versions(
+    id: String,
+    from: String,
+    to: Option<String>,
+    state: State<'_, NotesState>,
+) -> Result<NoteDiff, String> {
+    let store = state.lock().map_err(|e| e.to_string())?;
+    let note = store
+        .notes
+        .get(&id)
+        .or_else(|| store.trash.get(&id))
+        .ok_or_else(|| format!("Note {} not found", id))?;
+    let old = history::get(&id, &from)?.content;
+    let new = match to {
+        Some(version_id) => history::get(&id, &version_id)?.content,
+        // A locked note can only be compared while it is unlocked.
+        None => {
+            let view = note_view(&store, note);
+            if view.lock.as_ref().is_some_and(|lock| !lock.unlocked) {
+                return Err("Note is locked".to_string());
+            }
+            view.content
+        }
+    };
+    drop(store);
+    Ok(diff::diff_html(&old, &new))
+}
+
+/// Puts an earlier version's content back. The content it replaces is kept
+/// as a version first, so a restore can itself be undone.
+#[tauri::command]
+pub fn restore_note_version(
+    id: String,
+    version_id: String,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<Note, String> {
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    let note = store
+        .notes
+        .get_mut(&id)
+        .ok_or_else(|| format!("Note {} not found", id))?;
+    if note.lock.is_some() {
+        return Err("Note is locked".to_string());
+    }
+    let version = history::get(&id, &version_id)?;
+    history::record(&id, &note.content)?;
+    note.content = version.content;
+    note.updated_at = chrono::Utc::now().to_rfc3339();
+    let note = note.clone();
+    store.writer.put(&note);
+    drop(store);
+    // Open editors hold the old content and have to reload it.
+    emit_external_change(&app, "updated", &id);
+    Ok(note)
+}
+
+/// Shows a locked note's content for the rest of the session, or until
+/// `relock_note`. The content stays encrypted in storage.
+#[tauri::command]
+pub fn unlock_note(
+    id: String,
+    password: String,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<Note, String> {
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    let lock = store
+        .notes
+        .get(&id)
+        .ok_or("Note not found")?
+        .lock
+        .clone()
+        .ok_or("Note is not locked")?;
+    let key = crypto::unlock_key(&password, &lock.kdf).map_err(|_| "Wrong password")?;
+    store.unlocked_notes.insert(id.clone(), key);
+    let view = note_view(&store, &store.notes[&id]);
+    drop(store);
+    emit_notes_changed(&app, "updated", &id);
+    Ok(view)
+}
+
+/// Hides an unlocked note's content again without asking for the password.
+#[tauri::command]
+pub fn relock_note(id: String, app: AppHandle, state: State<'_, NotesState>) -> Result<(), String> {
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    store.unlocked_notes.remove(&id);
+    drop(store);
+    emit_notes_changed(&app, "updated", &id);
+    Ok(())
+}
+
+/// Decrypts a locked note for good and removes its password.
+#[tauri::command]
+pub fn remove_note_lock(
+    id: String,
+    password: String,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<Note, String> {
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    let note = store.notes.get_mut(&id).ok_or("Note not found")?;
+    let lock = note.lock.clone().ok_or("Note is not locked")?;
+    let key = crypto::unlock_key(&password, &lock.kdf).map_err(|_| "Wrong password")?;
+    note.content = crypto::open_text_with(Some(&key), &lock.content)?;
+    note.lock = None;
+    let note = note.clone();
+    store.writer.put(&note);
+    store.unlocked_notes.remove(&id);
+    drop(store);
+    emit_notes_changed(&app, "updated", &id);
+    Ok(note)
+}
+
 #[tauri::command]
 pub fn update_note_position(
     id: String,
@@ -100,12 +464,11 @@ pub fn update_note_position(
         note.y = y;
         note.width = width;
         note.height = height;
-        let snapshot = store.notes.clone();
-        drop(store);
-        save_notes(&snapshot);
+        let note = note.clone();
+        store.writer.put(&note);
         Ok(())
     } else {
-        Err(format!("Note not found"))
+        Err("Note not found".to_string())
     }
 }
 
@@ -120,9 +483,9 @@ pub fn set_window_level(
     if let Some(note) = store.notes.get_mut(&id) {
         note.window_level = level.clone();
         note.updated_at = chrono::Utc::now().to_rfc3339();
-        let snapshot = store.notes.clone();
+        let note = note.clone();
+        store.writer.put(&note);
         drop(store);
-        save_notes(&snapshot);
 
         if let Some(window) = app.get_webview_window(&id) {
             match level {
@@ -149,10 +512,736 @@ pub fn set_window_level(
         emit_notes_changed(&app, "updated", &id);
         Ok(())
     } else {
-        Err(format!("Note not found"))
+        Err("Note not found".to_string())
     }
 }
 
+#[tauri::command]
+pub fn get_storage_recovery(
+    state: State<'_, NotesState>,
+) -> Result<Option<RecoveryReport>, String> {
+    let store = state.lock().map_err(|e| e.to_string())?;
+    Ok(store.recovery.clone())
+}
+
+#[tauri::command]
+pub fn get_settings() -> Settings {
+    settings::current()
+}
+
+/// Copies every note into the chosen backend and makes it the active one.
+#[tauri::command]
+pub fn set_storage_backend(
+    backend: StorageBackend,
+    state: State<'_, NotesState>,
+) -> Result<Settings, String> {
+    let store = state.lock().map_err(|e| e.to_string())?;
+    if settings::current().storage_backend == backend {
+        return Ok(settings::current());
+    }
+    store.writer.flush()?;
+    let (mut repo, _) = storage::open_repository(backend)?;
+    let notes: Vec<Note> = store
+        .notes
+        .values()
+        .chain(store.trash.values())
+        .cloned()
+        .collect();
+    repo.replace_all(&notes)?;
+    store.writer.set_repo(repo)?;
+    settings::update(|s| s.storage_backend = backend)
+}
+
+#[tauri::command]
+pub fn get_storage_dir() -> DataDirInfo {
+    storage::data_dir_info()
+}
+
+/// Moves notes, media and everything else in the data directory to `target`
+/// and remembers the new location in settings.
+#[tauri::command]
+pub fn move_storage_dir(
+    target: String,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<DataDirInfo, String> {
+    // The bulk of the copy runs while notes can still be edited; only the
+    // final catch-up happens with the store locked and the writer closed.
+    let Some(mut pending) = storage::begin_storage_move(Path::new(&target))? else {
+        return Ok(storage::data_dir_info());
+    };
+    let store = state.lock().map_err(|e| e.to_string())?;
+    store.writer.close()?;
+    let switched = pending.sync().and_then(|_| {
+        let path = pending.target().to_path_buf();
+        settings::update(|s| s.data_dir = Some(path))
+            .map_err(|e| format!("Failed to save the new data directory: {}", e))
+    });
+    // The original is deleted only once settings point at the copy. On
+    // failure, dropping the pending move removes the copy instead.
+    let moved = switched.map(|_| pending.commit());
+
+    // Reopen wherever the store now lives, whether or not the move worked.
+    let (repo, _) = storage::open_repository(settings::current().storage_backend)?;
+    store.writer.set_repo(repo)?;
+    watcher::watch(&app);
+    migrate_media();
+
+    moved?;
+    Ok(storage::data_dir_info())
+}
+
+#[derive(Clone, Serialize)]
+pub struct WorkspaceList {
+    active: String,
+    workspaces: Vec<String>,
+}
+
+#[tauri::command]
+pub fn list_workspaces() -> WorkspaceList {
+    WorkspaceList {
+        active: storage::active_workspace(),
+        workspaces: storage::list_workspaces(),
+    }
+}
+
+#[tauri::command]
+pub fn create_workspace(name: String, app: AppHandle) -> Result<WorkspaceList, String> {
+    storage::create_workspace(&name)?;
+    tray::refresh_menu(&app);
+    Ok(list_workspaces())
+}
+
+/// Loads notes from the configured backend into the store and starts the
+/// background writer. Runs once, at startup or right after unlocking.
+pub fn load_store(app: &AppHandle) -> Result<(), String> {
+    let backend = settings::current().storage_backend;
+    let (repo, loaded) = match storage::open_repository(backend) {
+        Ok(opened) => opened,
+        Err(e) if backend != StorageBackend::Json => {
+            eprintln!(
+                "failed to open {:?} storage, falling back to notes.json: {}",
+                backend, e
+            );
+            storage::open_repository(StorageBackend::Json)?
+        }
+        Err(e) => return Err(e),
+    };
+
+    {
+        let state = app.state::<NotesState>();
+        let mut store = state.lock().map_err(|e| e.to_string())?;
+        store.set_notes(loaded.notes);
+        store.recovery = loaded.recovery.clone();
+        store.writer.set_repo(repo)?;
+        let error_handle = app.clone();
+        store.writer.start(move |error| {
+            let _ = error_handle.emit("storage-error", error);
+        });
+        purge_expired_trash(&mut store);
+    }
+    migrate_media();
+
+    if let Some(report) = loaded.recovery {
+        eprintln!(
+            "recovered {} notes from corrupt notes.json ({} lost), original kept at {}",
+            report.recovered,
+            report.lost.len(),
+            report.corrupt_file
+        );
+        let _ = app.emit("storage-recovered", report);
+    }
+    if let Some(reason) = storage::snapshot_hold() {
+        eprintln!("{}", reason);
+        let _ = app.emit("storage-error", reason);
+    }
+    watcher::watch(app);
+    schedule_maintenance(app);
+    schedule_backups(app);
+    Ok(())
+}
+
+/// Moves media no note refers to into quarantine and deletes what has been
+/// there longer than the `media_grace_days` setting.
+#[tauri::command]
+pub fn collect_media_garbage(
+    state: State<'_, NotesState>,
+) -> Result<storage::MediaGcReport, String> {
+    let store = state.lock().map_err(|e| e.to_string())?;
+    if crypto::is_locked() {
+        return Err("Notes are locked".to_string());
+    }
+    let mut referenced = HashSet::new();
+    // Trashed notes can still be restored, so their media is kept too.
+    for note in store.notes.values().chain(store.trash.values()) {
+        referenced.extend(storage::media_references(&note.content).map(String::from));
+        if let Some(lock) = &note.lock {
+            referenced.extend(lock.media.iter().cloned());
+        }
+    }
+    referenced.extend(history::media_references());
+    let grace = Duration::from_secs(settings::current().media_grace_days * 24 * 60 * 60);
+    // Held until the end so no workspace switch happens mid-run.
+    let report = storage::collect_media_garbage(&referenced, grace);
+    drop(store);
+    report
+}
+
+/// Renames the active workspace's UUID-named media to content names.
+fn migrate_media() {
+    match storage::migrate_media() {
+        Ok(0) => {}
+        Ok(count) => eprintln!("renamed {} media files to content names", count),
+        Err(e) => eprintln!("failed to migrate media: {}", e),
+    }
+}
+
+/// Purges expired trash and runs media garbage collection shortly after
+/// startup and then daily.
+fn schedule_maintenance(app: &AppHandle) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(MAINTENANCE_DELAY);
+        loop {
+            let purged = match app.state::<NotesState>().lock() {
+                Ok(mut store) => purge_expired_trash(&mut store),
+                Err(e) => {
+                    eprintln!("failed to purge trash: {}", e);
+                    Vec::new()
+                }
+            };
+            for id in &purged {
+                emit_notes_changed(&app, "purged", id);
+            }
+            match collect_media_garbage(app.state::<NotesState>()) {
+                Ok(report) if report.quarantined + report.restored + report.purged > 0 => {
+                    eprintln!(
+                        "media gc: quarantined {} ({} bytes), restored {}, purged {} ({} bytes reclaimed)",
+                        report.quarantined,
+                        report.quarantined_bytes,
+                        report.restored,
+                        report.purged,
+                        report.bytes_reclaimed
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("media gc failed: {}", e),
+            }
+            std::thread::sleep(MAINTENANCE_INTERVAL);
+        }
+    });
+}
+
+/// Writes the daily and weekly backups that are due and drops the oldest
+/// beyond the `daily_backups` and `weekly_backups` settings.
+fn run_due_backups(app: &AppHandle) {
+    if crypto::is_locked() {
+        return;
+    }
+    let settings = settings::current();
+    let state = app.state::<NotesState>();
+    // Only the copy is taken under the lock; the backup is written into the
+    // workspace it came from even if another becomes active meanwhile.
+    let (workspace, notes) = match state.lock() {
+        Ok(store) => (storage::workspace_dir(), all_notes(&store)),
+        Err(e) => {
+            eprintln!("failed to back up notes: {}", e);
+            return;
+        }
+    };
+    let mut created = false;
+    for (kind, keep) in [
+        (BackupKind::Daily, settings.daily_backups),
+        (BackupKind::Weekly, settings.weekly_backups),
+    ] {
+        if keep == 0 {
+            continue;
+        }
+        if !backup::is_due(&workspace, kind) {
+            continue;
+        }
+        match backup::create(&workspace, kind, &notes) {
+            Ok(_) => created = true,
+            Err(e) => {
+                eprintln!("{:?} backup failed: {}", kind, e);
+                continue;
+            }
+        }
+        backup::prune(&workspace, kind, keep);
+    }
+    // A note locked while the backup was written is still in it unlocked.
+    if created {
+        if let Ok(store) = state.lock() {
+            for note in &notes {
+                let now = store
+                    .notes
+                    .get(&note.id)
+                    .or_else(|| store.trash.get(&note.id));
+                let Some(now) = now.filter(|now| now.lock.is_some() && now.content != note.content)
+                else {
+                    continue;
+                };
+                if let Err(e) = backup::scrub_note(&workspace, now) {
+                    eprintln!("failed to remove a locked note from backups: {}", e);
+                }
+            }
+        }
+        tray::refresh_menu(app);
+    }
+}
+
+/// Checks hourly for due backups, starting shortly after startup.
+fn schedule_backups(app: &AppHandle) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(BACKUP_DELAY);
+        loop {
+            run_due_backups(&app);
+            std::thread::sleep(BACKUP_CHECK_INTERVAL);
+        }
+    });
+}
+
+/// Backups of the active workspace, newest first.
+#[tauri::command]
+pub fn list_backups() -> Vec<BackupInfo> {
+    backup::list()
+}
+
+/// Every note in the store, trashed ones included.
+fn all_notes(store: &NotesStore) -> Vec<Note> {
+    store
+        .notes
+        .values()
+        .chain(store.trash.values())
+        .cloned()
+        .collect()
+}
+
+/// Makes `notes` the whole store, trash included, and brings the windows in
+/// line: those of notes that are gone close, the rest reload.
+fn replace_notes(
+    app: &AppHandle,
+    mut store: MutexGuard<'_, NotesStore>,
+    notes: Vec<Note>,
+) -> Result<(), String> {
+    store.writer.flush()?;
+    let (mut repo, _) = storage::open_repository(settings::current().storage_backend)?;
+    repo.replace_all(&notes)?;
+    store.writer.set_repo(repo)?;
+
+    let showing = showing_notes(app, &store);
+    let previous = store.set_notes(notes.into_iter().map(|n| (n.id.clone(), n)).collect());
+    store.unlocked_notes.clear();
+    let reopen: Vec<Note> = showing
+        .iter()
+        .filter_map(|id| store.notes.get(id))
+        .cloned()
+        .collect();
+    drop(store);
+
+    for id in previous.keys() {
+        if reopen.iter().any(|note| &note.id == id) {
+            continue;
+        }
+        if let Some(window) = app.get_webview_window(id) {
+            window.destroy().map_err(|e| e.to_string())?;
+        }
+    }
+    if reopen.is_empty() {
+        open_startup_note(app);
+    }
+    for note in &reopen {
+        // Windows that stay open reload the note like after an outside edit.
+        emit_external_change(app, "updated", &note.id);
+    }
+    Ok(())
+}
+
+/// Replaces every note with those in backup `id` and reloads the windows
+/// that were showing. The current notes are backed up first, so the
+/// restore can be undone by restoring that backup.
+#[tauri::command]
+pub fn restore_backup(
+    id: String,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<(), String> {
+    if crypto::is_locked() {
+        return Err("Notes are locked".to_string());
+    }
+    let store = state.lock().map_err(|e| e.to_string())?;
+    backup::create_pre_restore(&all_notes(&store))?;
+    let notes = backup::restore(&id)?;
+    replace_notes(&app, store, notes)?;
+    let _ = app.emit("backup-restored", &id);
+    Ok(())
+}
+
+fn default_archive_name() -> String {
+    format!(
+        "stickynotes-{}-{}.zip",
+        storage::active_workspace(),
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    )
+}
+
+/// Asks where to save an archive and writes the notes and the media they
+/// use there. Trashed notes are left out. With encryption on, the user is
+/// first warned that the archive is not encrypted. `None` if they cancelled.
+/// Shows native dialogs, so it must not run on the main thread.
+pub fn export_archive_with_dialog(app: &AppHandle) -> Result<Option<ExportSummary>, String> {
+    if crypto::is_locked() {
+        return Err("Notes are locked".to_string());
+    }
+    if crypto::current_key().is_some() {
+        let export = app
+            .dialog()
+            .message(
+                "The archive is not encrypted. Anyone with the file can read every note \
+                 that is not locked with its own password.",
+            )
+            .title("Export Archive")
+            .kind(MessageDialogKind::Warning)
+            .buttons(MessageDialogButtons::OkCancelCustom(
+                "Export".to_string(),
+                "Cancel".to_string(),
+            ))
+            .blocking_show();
+        if !export {
+            return Ok(None);
+        }
+    }
+    let mut dialog = app
+        .dialog()
+        .file()
+        .set_title("Export Archive")
+        .add_filter("Zip archive", &["zip"])
+        .set_file_name(default_archive_name());
+    if let Some(dir) = dirs::download_dir().or_else(dirs::home_dir) {
+        dialog = dialog.set_directory(dir);
+    }
+    let Some(path) = dialog.blocking_save_file() else {
+        return Ok(None);
+    };
+    let path = path.into_path().map_err(|e| e.to_string())?;
+
+    let state = app.state::<NotesState>();
+    let store = state.lock().map_err(|e| e.to_string())?;
+    let mut notes: Vec<Note> = store.notes.values().cloned().collect();
+    drop(store);
+    notes.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    archive::export(&path, &notes).map(Some)
+}
+
+/// Lets the user pick where the archive goes, see
+/// `export_archive_with_dialog`.
+#[tauri::command]
+pub async fn export_archive(app: AppHandle) -> Result<Option<ExportSummary>, String> {
+    tauri::async_runtime::spawn_blocking(move || export_archive_with_dialog(&app))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Asks for an archive made by `export_archive` and, unless `mode` is
+/// given, whether to merge or replace, then brings in its notes and media.
+/// Merge adds them next to the current notes, giving a new id to any whose
+/// id is taken; replace swaps out every note after backing them up, like
+/// restoring a backup. `None` if the user cancelled. Shows native dialogs,
+/// so it must not run on the main thread.
+pub fn import_archive_with_dialog(
+    app: &AppHandle,
+    mode: Option<ImportMode>,
+) -> Result<Option<ImportSummary>, String> {
+    if crypto::is_locked() {
+        return Err("Notes are locked".to_string());
+    }
+    let path = app
+        .dialog()
+        .file()
+        .set_title("Import Archive")
+        .add_filter("Zip archive", &["zip"])
+        .blocking_pick_file();
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let path = path.into_path().map_err(|e| e.to_string())?;
+    let mode = match mode {
+        Some(mode) => mode,
+        None => {
+            let choice = app
+                .dialog()
+                .message(
+                    "Merge adds the archived notes next to the current ones. Replace swaps \
+                     out every note; the current ones are backed up first.",
+                )
+                .title("Import Archive")
+                .buttons(MessageDialogButtons::YesNoCancelCustom(
+                    "Merge".to_string(),
+                    "Replace".to_string(),
+                    "Cancel".to_string(),
+                ))
+                .blocking_show_with_result();
+            match choice {
+                MessageDialogResult::Yes => ImportMode::Merge,
+                MessageDialogResult::No => ImportMode::Replace,
+                MessageDialogResult::Custom(label) if label == "Merge" => ImportMode::Merge,
+                MessageDialogResult::Custom(label) if label == "Replace" => ImportMode::Replace,
+                _ => return Ok(None),
+            }
+        }
+    };
+
+    let contents = archive::import(&path)?;
+    let state = app.state::<NotesState>();
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    let mut summary = ImportSummary {
+        mode,
+        notes: contents.notes.len(),
+        skipped: 0,
+        renamed: 0,
+        media: contents.media,
+        rejected_media: contents.rejected_media,
+    };
+    match mode {
+        ImportMode::Merge => {
+            let merged = archive::merge(contents.notes, &[&store.notes, &store.trash]);
+            for note in &merged.added {
+                store.writer.put(note);
+                store.notes.insert(note.id.clone(), note.clone());
+            }
+            drop(store);
+            for note in &merged.added {
+                emit_notes_changed(app, "created", &note.id);
+            }
+            summary.notes = merged.added.len();
+            summary.skipped = merged.skipped;
+            summary.renamed = merged.renamed;
+        }
+        ImportMode::Replace => {
+            backup::create_pre_restore(&all_notes(&store))?;
+            replace_notes(app, store, contents.notes)?;
+        }
+    }
+    Ok(Some(summary))
+}
+
+/// Lets the user pick the archive, see `import_archive_with_dialog`.
+#[tauri::command]
+pub async fn import_archive(
+    mode: Option<ImportMode>,
+    app: AppHandle,
+) -> Result<Option<ImportSummary>, String> {
+    tauri::async_runtime::spawn_blocking(move || import_archive_with_dialog(&app, mode))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Checks the passphrase, then loads the encrypted store and replaces the
+/// unlock window with the startup note.
+#[tauri::command]
+pub fn unlock_store(passphrase: String, app: AppHandle) -> Result<(), String> {
+    let params = settings::current()
+        .encryption
+        .ok_or_else(|| "Encryption is not enabled".to_string())?;
+    // The unlock window can send the passphrase twice; the second call
+    // waits here and then finds the store already open.
+    let _unlocking = UNLOCKING.lock().map_err(|e| e.to_string())?;
+    if !crypto::is_locked() {
+        return Ok(());
+    }
+    let key = crypto::unlock_key(&passphrase, &params)?;
+    if !settings::current().encryption_complete {
+        // An earlier conversion stopped partway; seal what it left behind.
+        let finished = storage::reseal_data_dir(Some(&key), Some(&key))
+            .and_then(|_| settings::update(|s| s.encryption_complete = true));
+        if let Err(e) = finished {
+            eprintln!("failed to finish encrypting the store: {}", e);
+        }
+    }
+    crypto::set_key(Some(key));
+    if let Err(e) = load_store(&app) {
+        crypto::set_key(None);
+        return Err(e);
+    }
+    open_startup_note(&app);
+    if let Some(window) = app.get_webview_window(UNLOCK_WINDOW) {
+        window.destroy().map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Turns on encryption at rest and encrypts everything already stored. If
+/// an earlier attempt stopped halfway, calling this again with the same
+/// passphrase finishes it.
+#[tauri::command]
+pub fn enable_encryption(
+    passphrase: String,
+    state: State<'_, NotesState>,
+) -> Result<Settings, String> {
+    let current = settings::current();
+    let (key, params) = match current.encryption {
+        Some(_) if current.encryption_complete => {
+            return Err("Encryption is already enabled".to_string())
+        }
+        Some(params) => (crypto::unlock_key(&passphrase, &params)?, params),
+        None => crypto::new_key(&passphrase)?,
+    };
+
+    let store = state.lock().map_err(|e| e.to_string())?;
+    store.writer.close()?;
+    // Plaintext files still read fine once the key is set, so a conversion
+    // that stops halfway leaves a usable store, and enabling again with the
+    // same passphrase finishes it.
+    crypto::set_key(Some(key.clone()));
+    let result = settings::update(|s| {
+        s.encryption = Some(params.clone());
+        s.encryption_complete = false;
+    })
+    .inspect_err(|_| crypto::set_key(None))
+    .and_then(|_| storage::reseal_data_dir(None, Some(&key)))
+    .and_then(|_| settings::update(|s| s.encryption_complete = true));
+
+    let (repo, _) = storage::open_repository(settings::current().storage_backend)?;
+    store.writer.set_repo(repo)?;
+    result.map(|_| settings::current())
+}
+
+/// Decrypts everything in the data directory and turns encryption off.
+#[tauri::command]
+pub fn disable_encryption(
+    passphrase: String,
+    state: State<'_, NotesState>,
+) -> Result<Settings, String> {
+    let params = settings::current()
+        .encryption
+        .ok_or_else(|| "Encryption is not enabled".to_string())?;
+    let key = crypto::unlock_key(&passphrase, &params)?;
+
+    let store = state.lock().map_err(|e| e.to_string())?;
+    store.writer.close()?;
+    // Files become plaintext one by one, so stop refusing plaintext first.
+    let result = settings::update(|s| s.encryption_complete = false)
+        .and_then(|_| storage::reseal_data_dir(Some(&key), None))
+        .and_then(|_| settings::update(|s| s.encryption = None));
+    if result.is_ok() {
+        crypto::set_key(None);
+    }
+
+    let (repo, _) = storage::open_repository(settings::current().storage_backend)?;
+    store.writer.set_repo(repo)?;
+    result
+}
+
+/// Opens the most recently updated note centered on screen, or creates a
+/// first note if the store is empty.
+pub fn open_startup_note(app: &AppHandle) {
+    let state = app.state::<NotesState>();
+    let latest = match state.lock() {
+        Ok(store) => store
+            .notes
+            .values()
+            .max_by(|a, b| a.updated_at.cmp(&b.updated_at))
+            .cloned(),
+        Err(e) => {
+            eprintln!("failed to read notes: {}", e);
+            return;
+        }
+    };
+
+    if let Some(note) = latest {
+        if let Err(e) = open_note_window_centered(app, &note) {
+            eprintln!("failed to open note {}: {}", note.id, e);
+        }
+    } else if let Err(e) = create_note(app.clone(), state) {
+        eprintln!("failed to create initial note: {}", e);
+    }
+}
+
+/// Ids of the notes whose windows are visible.
+fn showing_notes(app: &AppHandle, store: &NotesStore) -> Vec<String> {
+    store
+        .notes
+        .keys()
+        .filter(|id| {
+            app.get_webview_window(id)
+                .and_then(|w| w.is_visible().ok())
+                .unwrap_or(false)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Closes the current workspace's windows, loads `name` into the store and
+/// reopens the windows that were showing when it was last active.
+#[tauri::command]
+pub fn switch_workspace(
+    name: String,
+    app: AppHandle,
+    state: State<'_, NotesState>,
+) -> Result<(), String> {
+    storage::validate_name(&name)?;
+    let previous = storage::active_workspace();
+    if name == previous {
+        return Ok(());
+    }
+    storage::create_workspace(&name)?;
+
+    let mut store = state.lock().map_err(|e| e.to_string())?;
+    let showing = showing_notes(&app, &store);
+
+    store.writer.close()?;
+    let backend = settings::current().storage_backend;
+    let opened = settings::update(|s| s.workspace = name.clone())
+        .and_then(|_| storage::open_repository(backend));
+    let (repo, loaded) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            // Stay on the previous workspace.
+            settings::update(|s| s.workspace = previous.clone()).ok();
+            let (repo, _) = storage::open_repository(backend)?;
+            store.writer.set_repo(repo)?;
+            return Err(e);
+        }
+    };
+    store.writer.set_repo(repo)?;
+    watcher::watch(&app);
+
+    let old_ids: Vec<String> = store.notes.keys().cloned().collect();
+    store.set_notes(loaded.notes);
+    store.recovery = loaded.recovery;
+    store.open_windows.insert(previous, showing);
+    let reopen: Vec<Note> = store
+        .open_windows
+        .get(&name)
+        .map(|ids| {
+            ids.iter()
+                .filter_map(|id| store.notes.get(id))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+    drop(store);
+
+    for id in old_ids {
+        if let Some(window) = app.get_webview_window(&id) {
+            window.destroy().map_err(|e| e.to_string())?;
+        }
+    }
+
+    if reopen.is_empty() {
+        open_startup_note(&app);
+    } else {
+        for note in &reopen {
+            open_note_window(&app, note)?;
+        }
+    }
+
+    tray::refresh_menu(&app);
+    let _ = app.emit("workspace-changed", &name);
+    Ok(())
+}
+
 #[tauri::command]
 pub fn show_note(id: String, app: AppHandle, state: State<'_, NotesState>) -> Result<(), String> {
     let store = state.lock().map_err(|e| e.to_string())?;
@@ -217,24 +1306,115 @@ pub fn close_note_window(id: String, app: AppHandle) -> Result<(), String> {
     Ok(())
 }
 
+/// Stores a pasted image after checking its content against `mime_type`
+/// and the `max_image_bytes` setting.
+#[tauri::command]
+pub fn save_image(data: String, mime_type: String) -> Result<String, ImageError> {
+    let max = settings::current().max_image_bytes;
+    // Four base64 characters carry three bytes; refuse before decoding.
+    let estimate = data.len() as u64 / 4 * 3;
+    if estimate > max + 3 {
+        return Err(ImageError::TooLarge {
+            size: estimate,
+            max,
+        });
+    }
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(&data)
+        .map_err(|e| ImageError::InvalidBase64 {
+            message: e.to_string(),
+        })?;
+
+    import::store_image(&bytes, &mime_type, max)
+}
+
+/// Stores a non-image file (document, text, audio or archive) along with the
+/// name it was attached under.
 #[tauri::command]
-pub fn save_image(data: String, mime_type: String) -> Result<String, String> {
+pub fn save_attachment(data: String, file_name: String) -> Result<Attachment, AttachmentError> {
+    let max = settings::current().max_attachment_bytes;
+    let estimate = data.len() as u64 / 4 * 3;
+    if estimate > max + 3 {
+        return Err(AttachmentError::TooLarge {
+            size: estimate,
+            max,
+        });
+    }
     let bytes = base64::engine::general_purpose::STANDARD
         .decode(&data)
-        .map_err(|e| format!("Invalid base64: {}", e))?;
-
-    let ext = match mime_type.as_str() {
-        "image/png" => "png",
-        "image/jpeg" | "image/jpg" => "jpg",
-        "image/gif" => "gif",
-        "image/webp" => "webp",
-        "image/svg+xml" => "svg",
-        _ => "png",
+        .map_err(|e| AttachmentError::InvalidBase64 {
+            message: e.to_string(),
+        })?;
+    attachment::save(&bytes, &file_name, max)
+}
+
+/// Paths dropped on a window recently, with when. `import_media` only reads
+/// files from here, so a webview cannot name any file it likes.
+static DROPPED: Mutex<Vec<(PathBuf, Instant)>> = Mutex::new(Vec::new());
+/// How long a drop may take to reach `import_media`.
+const DROP_TTL: Duration = Duration::from_secs(60);
+
+/// Records the paths of a native drag-and-drop, see `import_media`.
+pub fn allow_dropped(paths: &[PathBuf]) {
+    if let Ok(mut dropped) = DROPPED.lock() {
+        dropped.retain(|(_, at)| at.elapsed() < DROP_TTL);
+        let now = Instant::now();
+        dropped.extend(paths.iter().map(|path| (path.clone(), now)));
+    }
+}
+
+/// Copies a dropped file into media on this side, so the frontend never has
+/// to read it. Each dropped path can be imported once.
+#[tauri::command]
+pub fn import_media(path: String) -> Result<Imported, ImportError> {
+    let path = PathBuf::from(path);
+    let allowed = DROPPED.lock().is_ok_and(|mut dropped| {
+        dropped.retain(|(_, at)| at.elapsed() < DROP_TTL);
+        match dropped.iter().position(|(p, _)| *p == path) {
+            Some(i) => {
+                dropped.remove(i);
+                true
+            }
+            None => false,
+        }
+    });
+    if !allowed {
+        return Err(ImportError::NotDropped {
+            path: path.display().to_string(),
+        });
+    }
+    import::import_path(&path)
+}
+
+/// Stores a file sent as a raw IPC body. The name and mime type come in the
+/// `x-file-name` (percent-encoded) and `x-mime-type` headers.
+#[tauri::command]
+pub fn import_media_bytes(request: tauri::ipc::Request<'_>) -> Result<Imported, ImportError> {
+    let bytes = match request.body() {
+        InvokeBody::Raw(bytes) => bytes,
+        InvokeBody::Json(_) => return Err(ImportError::InvalidBody),
     };
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    };
+    let file_name = protocol::percent_decode(header("x-file-name")).unwrap_or_default();
+    import::import_bytes(bytes, &file_name, header("x-mime-type"))
+}
 
-    let filename = format!("{}.{}", uuid::Uuid::new_v4(), ext);
-    let path = media_dir().join(&filename);
-    fs::write(&path, &bytes).map_err(|e| format!("Failed to write image: {}", e))?;
+#[tauri::command]
+pub fn get_attachment(file: String) -> Result<Attachment, String> {
+    attachment::info(&file)
+}
 
-    Ok(filename)
+/// Opens an attachment in the application the system uses for its type.
+#[tauri::command]
+pub fn open_attachment(file: String, app: AppHandle) -> Result<(), String> {
+    let path = attachment::export_for_opening(&file)?;
+    app.opener()
+        .open_path(path.to_string_lossy(), None::<&str>)
+        .map_err(|e| format!("Failed to open attachment: {}", e))
 }
@@ -0,0 +1,306 @@
+//! Compares two versions of a note as plain text, so the history view can
+//! show what changed before an old version is restored.
+//!
+//! Note content is Tiptap HTML; it is reduced to one line per paragraph,
+//! heading or list item before diffing. Changed lines are paired up where
+//! they are similar enough, with the words that differ marked.
+
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+/// Unchanged lines shown around each hunk.
+const CONTEXT_LINES: usize = 3;
+
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "li",
+    "ul",
+    "ol",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "blockquote",
+    "pre",
+    "hr",
+    "tr",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+impl From<ChangeTag> for ChangeKind {
+    fn from(tag: ChangeTag) -> Self {
+        match tag {
+            ChangeTag::Equal => Self::Equal,
+            ChangeTag::Insert => Self::Insert,
+            ChangeTag::Delete => Self::Delete,
+        }
+    }
+}
+
+/// A run of text within a line; `changed` marks the words that differ from
+/// the paired line on the other side.
+#[derive(Debug, Clone, Serialize)]
+pub struct Segment {
+    pub changed: bool,
+    pub text: String,
+}
+
+/// One line of a hunk. Line numbers start at 1 and are missing on the side
+/// the line does not exist in.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffLine {
+    pub kind: ChangeKind,
+    pub old_line: Option<usize>,
+    pub new_line: Option<usize>,
+    pub segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NoteDiff {
+    pub hunks: Vec<Hunk>,
+    /// Lines only in the new text.
+    pub insertions: usize,
+    /// Lines only in the old text.
+    pub deletions: usize,
+}
+
+/// Line-by-line text of a note's HTML: one line per block, list items
+/// prefixed with "- ", images shown as "[image]" and entities decoded.
+pub fn plain_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut list_item = false;
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        push_text(&mut out, &rest[..start], &mut list_item);
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+        let tag = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "br" => out.push('\n'),
+            "img" => push_text(&mut out, "[image]", &mut list_item),
+            "li" if !closing => {
+                end_line(&mut out);
+                list_item = true;
+            }
+            name if BLOCK_TAGS.contains(&name) => end_line(&mut out),
+            _ => {}
+        }
+    }
+    push_text(&mut out, rest, &mut list_item);
+    end_line(&mut out);
+    out
+}
+
+fn end_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Appends decoded text, starting a pending list item's marker first.
+fn push_text(out: &mut String, text: &str, list_item: &mut bool) {
+    if text.is_empty() {
+        return;
+    }
+    if *list_item {
+        end_line(out);
+        out.push_str("- ");
+        *list_item = false;
+    }
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        match rest.find(';').filter(|&end| end <= 10) {
+            Some(end) => match decode_entity(&rest[1..end]) {
+                Some(c) => {
+                    out.push(c);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    out.push('&');
+                    rest = &rest[1..];
+                }
+            },
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let code = match entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+            {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => entity.strip_prefix('#')?.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+/// Diffs two texts line by line, with word-level marks inside changed lines.
+pub fn diff_text(old: &str, new: &str) -> NoteDiff {
+    let diff = TextDiff::from_lines(old, new);
+    let mut hunks = Vec::new();
+    let (mut insertions, mut deletions) = (0, 0);
+    for group in diff.grouped_ops(CONTEXT_LINES) {
+        let (Some(first), Some(last)) = (group.first(), group.last()) else {
+            continue;
+        };
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+        let mut lines = Vec::new();
+        for op in &group {
+            for change in diff.iter_inline_changes(op) {
+                match change.tag() {
+                    ChangeTag::Insert => insertions += 1,
+                    ChangeTag::Delete => deletions += 1,
+                    ChangeTag::Equal => {}
+                }
+                let mut segments: Vec<Segment> = change
+                    .iter_strings_lossy()
+                    .map(|(changed, text)| Segment {
+                        changed,
+                        text: text.into_owned(),
+                    })
+                    .collect();
+                // The line break belongs to the line, not to its text.
+                if let Some(last) = segments.last_mut() {
+                    last.text.truncate(last.text.trim_end_matches('\n').len());
+                    if last.text.is_empty() {
+                        segments.pop();
+                    }
+                }
+                lines.push(DiffLine {
+                    kind: change.tag().into(),
+                    old_line: change.old_index().map(|i| i + 1),
+                    new_line: change.new_index().map(|i| i + 1),
+                    segments,
+                });
+            }
+        }
+        hunks.push(Hunk {
+            old_start: old_range.start + 1,
+            old_len: old_range.len(),
+            new_start: new_range.start + 1,
+            new_len: new_range.len(),
+            lines,
+        });
+    }
+    NoteDiff {
+        hunks,
+        insertions,
+        deletions,
+    }
+}
+
+/// Diffs the plain text of two versions of a note's HTML.
+pub fn diff_html(old: &str, new: &str) -> NoteDiff {
+    diff_text(&plain_text(old), &plain_text(new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_one_line_per_block() {
+        let html = "<h1>Groceries</h1><ul><li><p>eggs &amp; milk</p></li><li><p>bread</p></li></ul>\
+                    <p>call <strong>Sam</strong><br>at 5</p><p><img src=\"http://media.localhost/a.png\"></p>";
+        assert_eq!(
+            plain_text(html),
+            "Groceries\n- eggs & milk\n- bread\ncall Sam\nat 5\n[image]\n"
+        );
+        assert_eq!(
+            plain_text("<p>a &lt;b&gt; &#233;&#x41; &bogus; & c</p>"),
+            "a <b> éA &bogus; & c\n"
+        );
+        assert_eq!(plain_text(""), "");
+    }
+
+    #[test]
+    fn reports_line_and_word_changes() {
+        let diff = diff_html(
+            "<p>one</p><p>the quick brown fox</p><p>three</p>",
+            "<p>one</p><p>the quick red fox</p><p>three</p><p>four</p>",
+        );
+        assert_eq!((diff.insertions, diff.deletions), (2, 1));
+        assert_eq!(diff.hunks.len(), 1);
+        let hunk = &diff.hunks[0];
+        assert_eq!((hunk.old_start, hunk.old_len), (1, 3));
+        assert_eq!((hunk.new_start, hunk.new_len), (1, 4));
+
+        let deleted = hunk
+            .lines
+            .iter()
+            .find(|l| l.kind == ChangeKind::Delete)
+            .unwrap();
+        assert_eq!(deleted.old_line, Some(2));
+        let changed: Vec<&str> = deleted
+            .segments
+            .iter()
+            .filter(|s| s.changed)
+            .map(|s| s.text.as_str())
+            .collect();
+        assert_eq!(changed, ["brown"]);
+
+        let added = hunk.lines.last().unwrap();
+        assert_eq!(added.kind, ChangeKind::Insert);
+        assert_eq!(added.new_line, Some(4));
+        assert_eq!(added.segments[0].text, "four");
+    }
+
+    #[test]
+    fn identical_texts_have_no_hunks() {
+        let diff = diff_html("<p>same</p>", "<p>same</p>");
+        assert!(diff.hunks.is_empty());
+        assert_eq!((diff.insertions, diff.deletions), (0, 0));
+    }
+}
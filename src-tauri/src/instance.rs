@@ -0,0 +1,216 @@
+//! Keeps a single running instance per notes store.
+//!
+//! The first launch takes an exclusive lock on `instance.lock` and listens on
+//! a local socket. Later launches find the lock taken, send their arguments
+//! over the socket and exit, so only one process ever writes the notes.
+//!
+//! On Unix the socket is a file in the user's private runtime directory, or
+//! next to the lock with permissions for this user only. Elsewhere it is a
+//! named pipe, which other users cannot write to.
+
+use crate::commands;
+use crate::notes::NotesState;
+use crate::settings;
+use crate::storage::{self, DataDirSource};
+use crate::window;
+use fs4::fs_std::FileExt;
+#[cfg(not(unix))]
+use interprocess::local_socket::GenericNamespaced;
+use interprocess::local_socket::{prelude::*, ListenerOptions, Name, Stream};
+#[cfg(unix)]
+use interprocess::local_socket::{GenericFilePath, ToFsName};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+const LOCK_FILE: &str = "instance.lock";
+// The running instance may still be starting up when a second launch
+// connects, so keep trying for a little while.
+const CONNECT_ATTEMPTS: u32 = 20;
+const CONNECT_RETRY: Duration = Duration::from_millis(100);
+// A launch sends one short line right after connecting.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_MESSAGE: u64 = 64 * 1024;
+
+/// Held for as long as this process is the running instance.
+pub struct InstanceLock {
+    _file: File,
+}
+
+/// A data directory fixed by flag, environment or portable mode is its own
+/// store. Otherwise the lock sits next to settings.json, which stays put
+/// when the data directory is moved.
+fn lock_dir() -> PathBuf {
+    let info = storage::data_dir_info();
+    match info.source {
+        DataDirSource::Cli | DataDirSource::Env | DataDirSource::Portable => {
+            PathBuf::from(info.path)
+        }
+        DataDirSource::Settings | DataDirSource::Default => settings::settings_dir(),
+    }
+}
+
+// FNV-1a, so every build derives the same socket name from the same path.
+fn stable_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn socket_file() -> String {
+    let dir = lock_dir();
+    let dir = dir.canonicalize().unwrap_or(dir);
+    format!(
+        "com.stickynotes.app-{:016x}.sock",
+        stable_hash(dir.to_string_lossy().as_bytes())
+    )
+}
+
+#[cfg(unix)]
+fn socket_name() -> io::Result<Name<'static>> {
+    let path = match dirs::runtime_dir() {
+        Some(dir) => dir.join(socket_file()),
+        None => lock_dir().join("instance.sock"),
+    };
+    path.to_fs_name::<GenericFilePath>()
+}
+
+#[cfg(not(unix))]
+fn socket_name() -> io::Result<Name<'static>> {
+    socket_file().to_ns_name::<GenericNamespaced>()
+}
+
+/// Takes the instance lock, or returns `None` if another process holds it.
+pub fn acquire() -> io::Result<Option<InstanceLock>> {
+    let dir = lock_dir();
+    fs::create_dir_all(&dir)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    if file.try_lock_exclusive()? {
+        Ok(Some(InstanceLock { _file: file }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Hands this launch's arguments to the running instance.
+pub fn forward(args: &[String]) -> io::Result<()> {
+    let name = socket_name()?;
+    let mut attempt = 0;
+    let mut stream = loop {
+        match Stream::connect(name.borrow()) {
+            Ok(stream) => break stream,
+            Err(_) if attempt < CONNECT_ATTEMPTS => {
+                attempt += 1;
+                std::thread::sleep(CONNECT_RETRY);
+            }
+            Err(e) => return Err(e),
+        }
+    };
+    let mut message = serde_json::to_string(args)?;
+    message.push('\n');
+    stream.write_all(message.as_bytes())
+}
+
+/// Accepts arguments from later launches for as long as the app runs.
+pub fn listen(app: &AppHandle) -> io::Result<()> {
+    // We hold the lock, so a socket left behind by a crashed instance can
+    // safely be replaced.
+    let options = ListenerOptions::new()
+        .name(socket_name()?)
+        .try_overwrite(true);
+    #[cfg(unix)]
+    let options = {
+        use interprocess::os::unix::local_socket::ListenerOptionsExt;
+        options.mode(0o600)
+    };
+    let listener = options.create_sync()?;
+
+    let app = app.clone();
+    std::thread::spawn(move || {
+        for conn in listener.incoming() {
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    eprintln!("failed to accept instance connection: {}", e);
+                    continue;
+                }
+            };
+            // A client that connects and never sends must not hold up the
+            // next launch.
+            let app = app.clone();
+            std::thread::spawn(move || read_forwarded(&app, conn));
+        }
+    });
+    Ok(())
+}
+
+fn read_forwarded(app: &AppHandle, conn: Stream) {
+    if let Err(e) = conn.set_recv_timeout(Some(READ_TIMEOUT)) {
+        eprintln!("failed to set instance connection timeout: {}", e);
+        return;
+    }
+    let mut line = String::new();
+    if let Err(e) = BufReader::new(conn).take(MAX_MESSAGE).read_line(&mut line) {
+        eprintln!("failed to read forwarded arguments: {}", e);
+        return;
+    }
+    match serde_json::from_str::<Vec<String>>(&line) {
+        Ok(args) => handle_args(app, &args, true),
+        Err(e) => eprintln!("ignoring malformed instance message: {}", e),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    NewNote,
+    ShowAll,
+    HideAll,
+}
+
+fn parse_actions(args: &[String]) -> Vec<Action> {
+    args.iter()
+        .filter_map(|arg| match arg.trim_start_matches('-') {
+            "new-note" | "new" => Some(Action::NewNote),
+            "show-all" | "show" => Some(Action::ShowAll),
+            "hide-all" | "hide" => Some(Action::HideAll),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Runs the actions named in `args`. A forwarded launch without any brings
+/// the notes to the front, like reopening the app would.
+pub fn handle_args(app: &AppHandle, args: &[String], forwarded: bool) {
+    let mut actions = parse_actions(args);
+    if actions.is_empty() && forwarded {
+        actions.push(Action::ShowAll);
+    }
+    if actions.is_empty() {
+        return;
+    }
+
+    if storage::crypto::is_locked() {
+        if let Err(e) = window::open_unlock_window(app) {
+            eprintln!("failed to open unlock window: {}", e);
+        }
+        return;
+    }
+
+    for action in actions {
+        let state = app.state::<NotesState>();
+        let result = match action {
+            Action::NewNote => commands::create_note(app.clone(), state).map(|_| ()),
+            Action::ShowAll => commands::show_all_notes(app.clone(), state),
+            Action::HideAll => commands::hide_all_notes(app.clone(), state),
+        };
+        if let Err(e) = result {
+            eprintln!("failed to run {:?}: {}", action, e);
+        }
+    }
+}
@@ -1,18 +1,40 @@
 mod commands;
+mod diff;
+mod instance;
 mod notes;
+mod protocol;
+mod settings;
 mod storage;
 mod tray;
+mod watcher;
 mod window;
 
 use notes::{NotesState, NotesStore};
 use std::sync::Mutex;
-use tauri::Manager;
-use tauri::http::Response as HttpResponse;
+use tauri::{DragDropEvent, Manager, RunEvent, WindowEvent};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Only one process may own the notes; a second launch hands its
+    // arguments to the first and exits.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let _instance = match instance::acquire() {
+        Ok(Some(lock)) => Some(lock),
+        Ok(None) => {
+            if let Err(e) = instance::forward(&args) {
+                eprintln!("another instance is running but could not be reached: {}", e);
+            }
+            return;
+        }
+        Err(e) => {
+            eprintln!("failed to check for a running instance: {}", e);
+            None
+        }
+    };
+
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .manage(Mutex::new(NotesStore::new()) as NotesState)
         .invoke_handler(tauri::generate_handler![
@@ -20,7 +42,22 @@ pub fn run() {
             commands::list_notes,
             commands::get_note,
             commands::update_note,
+            commands::lock_note,
+            commands::unlock_note,
+            commands::relock_note,
+            commands::remove_note_lock,
+            commands::list_note_versions,
+            commands::get_note_version,
+            commands::restore_note_version,
+            commands::diff_note_versions,
+            commands::list_backups,
+            commands::restore_backup,
+            commands::export_archive,
+            commands::import_archive,
             commands::delete_note,
+            commands::list_trash,
+            commands::restore_note,
+            commands::empty_trash,
             commands::show_note,
             commands::update_note_position,
             commands::set_window_level,
@@ -28,70 +65,50 @@ pub fn run() {
             commands::hide_all_notes,
             commands::close_note_window,
             commands::save_image,
+            commands::save_attachment,
+            commands::import_media,
+            commands::import_media_bytes,
+            commands::get_attachment,
+            commands::open_attachment,
+            commands::collect_media_garbage,
+            commands::get_storage_recovery,
+            commands::get_settings,
+            commands::set_storage_backend,
+            commands::get_storage_dir,
+            commands::move_storage_dir,
+            commands::list_workspaces,
+            commands::create_workspace,
+            commands::switch_workspace,
+            commands::unlock_store,
+            commands::enable_encryption,
+            commands::disable_encryption,
         ])
-        .register_uri_scheme_protocol("media", |_ctx, request| {
-            let path = request.uri().path();
-            let filename = path.trim_start_matches('/');
-            let file_path = storage::media_dir().join(filename);
-
-            match std::fs::read(&file_path) {
-                Ok(bytes) => {
-                    let mime = match file_path.extension().and_then(|e| e.to_str()) {
-                        Some("png") => "image/png",
-                        Some("jpg") | Some("jpeg") => "image/jpeg",
-                        Some("gif") => "image/gif",
-                        Some("webp") => "image/webp",
-                        Some("svg") => "image/svg+xml",
-                        _ => "application/octet-stream",
-                    };
-                    HttpResponse::builder()
-                        .header("content-type", mime)
-                        .body(bytes)
-                        .unwrap()
-                }
-                Err(_) => HttpResponse::builder()
-                    .status(404)
-                    .body(Vec::new())
-                    .unwrap(),
+        .register_uri_scheme_protocol("media", |_ctx, request| protocol::handle(&request))
+        // Files dropped from the desktop; the note window imports them by path.
+        .on_window_event(|_window, event| {
+            if let WindowEvent::DragDrop(DragDropEvent::Drop { paths, .. }) = event {
+                commands::allow_dropped(paths);
             }
         })
-        .setup(|app| {
+        .setup(move |app| {
             let handle = app.handle().clone();
 
-            // Load saved notes
-            let saved = storage::load_notes();
-
-            // Initialize store with saved notes
-            {
-                let state = handle.state::<NotesState>();
-                let mut store = state.lock().unwrap();
-                store.notes = saved;
-            }
-
             // Setup system tray
             tray::setup_tray(&handle)?;
 
-            // Open only the most recently updated note at startup (or create one if empty)
-            {
-                let state = handle.state::<NotesState>();
-                let store = state.lock().unwrap();
-                let latest = store
-                    .notes
-                    .values()
-                    .max_by(|a, b| a.updated_at.cmp(&b.updated_at))
-                    .cloned();
-                drop(store);
+            // With encryption on, nothing is read until the passphrase is
+            // entered; the unlock window loads the store itself.
+            if storage::crypto::is_locked() {
+                window::open_unlock_window(&handle)?;
+            } else {
+                commands::load_store(&handle)?;
+                // Open only the most recently updated note at startup (or create one if empty)
+                commands::open_startup_note(&handle);
+                instance::handle_args(&handle, &args, false);
+            }
 
-                if let Some(note) = latest {
-                    if let Err(e) = window::open_note_window_centered(&handle, &note) {
-                        eprintln!("failed to open note {}: {}", note.id, e);
-                    }
-                } else {
-                    let state = handle.state::<NotesState>();
-                    if let Err(e) = commands::create_note(handle.clone(), state) {
-                        eprintln!("failed to create initial note: {}", e);
-                    }
-                }
+            if let Err(e) = instance::listen(&handle) {
+                eprintln!("failed to listen for other instances: {}", e);
             }
 
             // Register global shortcut: Cmd+Shift+N
@@ -111,6 +128,17 @@ pub fn run() {
 
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Make sure queued edits reach disk before the process goes away
+            if let RunEvent::Exit = event {
+                let state = app.state::<NotesState>();
+                let writer = state.lock().map(|store| store.writer.clone());
+                if let Ok(writer) = writer {
+                    writer.shutdown();
+                }
+                storage::attachment::remove_opened();
+            }
+        });
 }
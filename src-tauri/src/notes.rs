@@ -1,66 +1,160 @@
+use crate::storage::crypto::{KdfParams, Key};
+use crate::storage::{NoteWriter, RecoveryReport};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum WindowLevel {
+    #[default]
     Normal,
     #[serde(rename = "alwaysOnTop")]
     AlwaysOnTop,
     Desktop,
 }
 
-impl Default for WindowLevel {
-    fn default() -> Self {
-        WindowLevel::Normal
-    }
+/// What `get_note` and `list_notes` show in place of a locked note's content.
+pub const LOCKED_PLACEHOLDER: &str = "<p>[locked]</p>";
+
+/// A per-note password lock. While a note is locked its `content` is empty
+/// and the real content lives here, sealed with a key derived from the
+/// note's password.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteLock {
+    pub kdf: KdfParams,
+    pub content: String,
+    /// Media files the sealed content refers to, kept in the clear so media
+    /// garbage collection does not need the password.
+    #[serde(default)]
+    pub media: Vec<String>,
+    /// Set on notes handed to the frontend while the note is unlocked for
+    /// this session. Never read back from storage.
+    #[serde(default, skip_deserializing)]
+    pub unlocked: bool,
 }
 
+/// Fields missing from a stored note fall back to those of a new note,
+/// except `id`: an entry without one is left to recovery rather than given a
+/// different id on every load.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Note {
     pub id: String,
+    #[serde(default)]
     pub content: String,
+    #[serde(default = "default_bg_color")]
     pub bg_color: String,
+    #[serde(default = "default_fg_color")]
     pub fg_color: String,
+    #[serde(default = "default_position")]
     pub x: f64,
+    #[serde(default = "default_position")]
     pub y: f64,
+    #[serde(default = "default_width")]
     pub width: f64,
+    #[serde(default = "default_height")]
     pub height: f64,
+    #[serde(default)]
     pub window_level: WindowLevel,
+    #[serde(default = "now")]
     pub created_at: String,
+    #[serde(default = "now")]
     pub updated_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock: Option<NoteLock>,
+    /// Set while the note is in the trash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+}
+
+fn default_bg_color() -> String {
+    "#0a0e14".to_string()
+}
+
+fn default_fg_color() -> String {
+    "#00ff88".to_string()
+}
+
+fn default_position() -> f64 {
+    100.0
+}
+
+fn default_width() -> f64 {
+    320.0
+}
+
+fn default_height() -> f64 {
+    280.0
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339()
 }
 
 impl Note {
     pub fn new() -> Self {
-        let now = chrono::Utc::now().to_rfc3339();
+        let now = now();
         Note {
             id: uuid::Uuid::new_v4().to_string(),
             content: String::new(),
-            bg_color: "#0a0e14".to_string(),
-            fg_color: "#00ff88".to_string(),
-            x: 100.0,
-            y: 100.0,
-            width: 320.0,
-            height: 280.0,
+            bg_color: default_bg_color(),
+            fg_color: default_fg_color(),
+            x: default_position(),
+            y: default_position(),
+            width: default_width(),
+            height: default_height(),
             window_level: WindowLevel::Normal,
             created_at: now.clone(),
             updated_at: now,
+            lock: None,
+            deleted_at: None,
         }
     }
 }
 
+impl Default for Note {
+    fn default() -> Self {
+        Note::new()
+    }
+}
+
 pub struct NotesStore {
     pub notes: HashMap<String, Note>,
+    /// Deleted notes, kept until restored or purged. They are stored with
+    /// the rest, told apart by `deleted_at`.
+    pub trash: HashMap<String, Note>,
+    /// Persists changes to `notes` in the background.
+    pub writer: Arc<NoteWriter>,
+    /// Set when the last load had to salvage a corrupt notes.json.
+    pub recovery: Option<RecoveryReport>,
+    /// Note windows that were on screen when each workspace was switched
+    /// away from, so switching back can reopen them.
+    pub open_windows: HashMap<String, Vec<String>>,
+    /// Keys for locked notes the user unlocked this session, by note id.
+    pub unlocked_notes: HashMap<String, Key>,
 }
 
 impl NotesStore {
     pub fn new() -> Self {
         NotesStore {
             notes: HashMap::new(),
+            trash: HashMap::new(),
+            writer: NoteWriter::new(),
+            recovery: None,
+            open_windows: HashMap::new(),
+            unlocked_notes: HashMap::new(),
         }
     }
+
+    /// Replaces the store's contents with freshly loaded notes, sorting
+    /// trashed ones into `trash`. Returns the live notes it replaced.
+    pub fn set_notes(&mut self, notes: HashMap<String, Note>) -> HashMap<String, Note> {
+        let (trash, live) = notes
+            .into_iter()
+            .partition(|(_, note)| note.deleted_at.is_some());
+        self.trash = trash;
+        std::mem::replace(&mut self.notes, live)
+    }
 }
 
 pub type NotesState = Mutex<NotesStore>;
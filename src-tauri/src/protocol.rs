@@ -0,0 +1,286 @@
+//! Serves `http://media.localhost/<file>` to the note windows.
+//!
+//! Only plain file names that resolve inside the media directory are served
+//! (see `storage::resolve_media`). Responses carry an ETag so a window that
+//! re-renders a note can revalidate instead of re-reading and decrypting the
+//! file, and a single `Range` is honoured so large attachments can be
+//! streamed and seeked. Only the requested part of a plain file is read; a
+//! sealed file is decrypted in full, since it is sealed in one piece.
+
+use crate::storage::attachment;
+use crate::storage::image::{ImageFormat, Variant};
+use crate::storage::{self, crypto, MediaLookupError, MediaVariant};
+use std::fs::{self, File, Metadata};
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::time::UNIX_EPOCH;
+use tauri::http::{header, Request, Response, StatusCode};
+
+// A content-addressed name can never point at different bytes.
+const CACHE_IMMUTABLE: &str = "private, max-age=31536000, immutable";
+const CACHE_REVALIDATE: &str = "no-cache";
+// Enough to tell a sealed file from a plain one.
+const SNIFF_LEN: u64 = 16;
+// Longest part of a file sent for one range request.
+const MAX_RANGE_LEN: u64 = 8 * 1024 * 1024;
+// Uploads are sanitized, but an SVG opened directly must still not be able
+// to run script or fetch anything.
+const CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; img-src data:";
+
+pub fn handle(request: &Request<Vec<u8>>) -> Response<Vec<u8>> {
+    serve(request)
+        .unwrap_or_else(|status| Response::builder().status(status).body(Vec::new()).unwrap())
+}
+
+fn serve(request: &Request<Vec<u8>>) -> Result<Response<Vec<u8>>, StatusCode> {
+    let name = percent_decode(request.uri().path().trim_start_matches('/'))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let original = storage::resolve_media(&name).map_err(|e| match e {
+        MediaLookupError::Invalid => StatusCode::BAD_REQUEST,
+        MediaLookupError::NotFound => StatusCode::NOT_FOUND,
+        MediaLookupError::OutsideMediaDir => StatusCode::FORBIDDEN,
+    })?;
+    // `?size=display` or `?size=thumb` picks a downscaled copy when the
+    // image is large enough to have one.
+    let variant = request.uri().query().and_then(Variant::from_query);
+    let (path, pending) = match variant.map(|v| storage::media_variant_path(&original, v)) {
+        Some(MediaVariant::Ready(path)) => (path, false),
+        Some(MediaVariant::Pending) => (original, true),
+        Some(MediaVariant::Original) | None => (original, false),
+    };
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+
+    let metadata = fs::metadata(&path).map_err(|_| StatusCode::NOT_FOUND)?;
+    let etag = etag(&metadata);
+    // The original stands in for a variant that is still being made; the
+    // next request should pick up the real one.
+    let cache_control = if storage::is_content_name(file_name) && !pending {
+        CACHE_IMMUTABLE
+    } else {
+        CACHE_REVALIDATE
+    };
+    let not_modified = request
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+    if not_modified {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .header(header::ETAG, &etag)
+            .header(header::CACHE_CONTROL, cache_control)
+            .body(Vec::new())
+            .unwrap());
+    }
+
+    let mut file = File::open(&path).map_err(|_| StatusCode::NOT_FOUND)?;
+    let mut head = Vec::new();
+    (&mut file)
+        .take(SNIFF_LEN)
+        .read_to_end(&mut head)
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    let refuse = |e: String| {
+        eprintln!("failed to decrypt media {}: {}", name, e);
+        StatusCode::FORBIDDEN
+    };
+    // A sealed file is one piece of ciphertext and has to be decrypted in
+    // full, even for a range. Plain files are read from where the range
+    // starts.
+    let decrypted = if crypto::is_sealed(&head) {
+        let mut data = head;
+        file.read_to_end(&mut data)
+            .map_err(|_| StatusCode::NOT_FOUND)?;
+        Some(crypto::open(&data).map_err(refuse)?)
+    } else {
+        // Checks that plaintext is allowed in this store at all.
+        crypto::open(&head).map_err(refuse)?;
+        None
+    };
+    let mime = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(|ext| {
+            ImageFormat::from_ext(ext)
+                .map(ImageFormat::mime)
+                .or_else(|| attachment::mime_for_ext(ext))
+        })
+        .unwrap_or("application/octet-stream");
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime)
+        .header(header::ETAG, &etag)
+        .header(header::CACHE_CONTROL, cache_control)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CONTENT_SECURITY_POLICY, CONTENT_SECURITY_POLICY);
+
+    let len = match &decrypted {
+        Some(bytes) => bytes.len() as u64,
+        None => metadata.len(),
+    };
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| parse_range(v, len));
+    let response = match range {
+        Some(Ok(Some(range))) => {
+            // The player asks again for whatever is left.
+            let range = range.start..range.end.min(range.start + MAX_RANGE_LEN);
+            let body = match &decrypted {
+                Some(bytes) => bytes[range.start as usize..range.end as usize].to_vec(),
+                None => read_range(&mut file, &range).map_err(|_| StatusCode::NOT_FOUND)?,
+            };
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, len),
+                )
+                .body(body)
+        }
+        Some(Err(())) => builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+            .body(Vec::new()),
+        // No range, or one we do not support: send the whole file.
+        _ => match decrypted {
+            Some(bytes) => builder.body(bytes),
+            None => {
+                builder.body(read_range(&mut file, &(0..len)).map_err(|_| StatusCode::NOT_FOUND)?)
+            }
+        },
+    };
+    Ok(response.unwrap())
+}
+
+fn read_range(file: &mut File, range: &Range<u64>) -> std::io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(range.start))?;
+    let mut out = Vec::with_capacity((range.end - range.start) as usize);
+    file.take(range.end - range.start).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Built from the stored file's size and modification time, at least one of
+/// which changes whenever its content does.
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+pub(crate) fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Parses a single `bytes=` range against a body of `len` bytes.
+/// `Ok(None)` means the header should be ignored (another unit, or several
+/// ranges); `Err` means the range cannot be satisfied.
+fn parse_range(header: &str, len: u64) -> Result<Option<Range<u64>>, ()> {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return Ok(None),
+        // The last `n` bytes.
+        ("", suffix) => {
+            let n: u64 = suffix.parse().map_err(|_| ())?;
+            if n == 0 {
+                return Err(());
+            }
+            len.saturating_sub(n)..len
+        }
+        (start, end) => {
+            let start: u64 = start.parse().map_err(|_| ())?;
+            let end = match end {
+                "" => len,
+                end => end
+                    .parse::<u64>()
+                    .map_err(|_| ())?
+                    .saturating_add(1)
+                    .min(len),
+            };
+            start..end
+        }
+    };
+    if range.start >= len || range.start >= range.end {
+        return Err(());
+    }
+    Ok(Some(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_encoded_traversal_so_it_can_be_rejected() {
+        assert_eq!(
+            percent_decode("%2e%2e%2fsettings.json").unwrap(),
+            "../settings.json"
+        );
+        assert_eq!(percent_decode("..%5Csecret").unwrap(), "..\\secret");
+        assert_eq!(percent_decode("a%00b").unwrap(), "a\0b");
+        assert_eq!(percent_decode("bad%2"), None);
+        assert_eq!(percent_decode("bad%zz"), None);
+        let _dir = storage::test_dir();
+        for encoded in ["%2e%2e%2fsettings.json", "%2Fetc%2Fpasswd", "..%5Csecret"] {
+            let name = percent_decode(encoded).unwrap();
+            assert_eq!(
+                storage::resolve_media(&name),
+                Err(MediaLookupError::Invalid),
+                "{}",
+                encoded
+            );
+        }
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some(0..100)));
+        assert_eq!(parse_range("bytes=500-", 1000), Ok(Some(500..1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok(Some(0..1000)));
+    }
+
+    #[test]
+    fn ignores_unsupported_ranges() {
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=-", 1000), Ok(None));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=5-2", 1000), Err(()));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(()));
+        assert_eq!(parse_range("bytes=a-b", 1000), Err(()));
+    }
+}
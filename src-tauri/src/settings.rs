@@ -0,0 +1,147 @@
+use crate::storage::crypto::KdfParams;
+use crate::storage::{portable_dir, write_atomic};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock, RwLock};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Json,
+    Sqlite,
+    /// One `<id>.md` file per note under `notes/`.
+    Markdown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// How many timestamped copies of notes.json to keep in `generations/`.
+    pub backup_generations: usize,
+    pub storage_backend: StorageBackend,
+    /// Where notes and media live. Overridden by `--data-dir`,
+    /// `STICKYNOTES_DATA_DIR` and portable mode.
+    pub data_dir: Option<PathBuf>,
+    /// Name of the active workspace; see `storage::list_workspaces`.
+    pub workspace: String,
+    /// Set when encryption at rest is on; the key itself is never stored.
+    pub encryption: Option<KdfParams>,
+    /// Set once everything has been encrypted. From then on unencrypted
+    /// files are refused instead of read as left over from the conversion.
+    pub encryption_complete: bool,
+    /// How long unreferenced media sits in quarantine before it is deleted.
+    pub media_grace_days: u64,
+    /// Largest image `save_image` accepts, in bytes.
+    pub max_image_bytes: u64,
+    /// Largest file `save_attachment` accepts, in bytes.
+    pub max_attachment_bytes: u64,
+    /// Days a deleted note stays in the trash; 0 keeps it until emptied.
+    pub trash_retention_days: u64,
+    /// Minutes between automatic snapshots of a note in its history. Edits
+    /// that replace most of a note are snapshotted right away.
+    pub history_interval_minutes: u64,
+    /// Most versions kept per note.
+    pub history_max_versions: usize,
+    /// Most bytes of history kept per note; the oldest versions go first.
+    pub history_max_bytes: u64,
+    /// Daily backups kept in `backups/`; 0 turns them off.
+    pub daily_backups: usize,
+    /// Weekly backups kept in `backups/`; 0 turns them off.
+    pub weekly_backups: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            backup_generations: 10,
+            storage_backend: StorageBackend::Json,
+            data_dir: None,
+            workspace: "default".to_string(),
+            encryption: None,
+            encryption_complete: false,
+            media_grace_days: 7,
+            max_image_bytes: 10 * 1024 * 1024,
+            max_attachment_bytes: 50 * 1024 * 1024,
+            trash_retention_days: 30,
+            history_interval_minutes: 10,
+            history_max_versions: 50,
+            history_max_bytes: 2 * 1024 * 1024,
+            daily_backups: 7,
+            weekly_backups: 4,
+        }
+    }
+}
+
+static SETTINGS: OnceLock<Mutex<Settings>> = OnceLock::new();
+static DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+fn default_dir() -> PathBuf {
+    portable_dir().unwrap_or_else(|| {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("com.stickynotes.app")
+    })
+}
+
+/// The directory holding settings.json. Unlike the data directory, it never
+/// moves.
+pub fn settings_dir() -> PathBuf {
+    let cached = DIR.read().ok().and_then(|d| d.clone());
+    let dir = cached.unwrap_or_else(|| {
+        let dir = default_dir();
+        if let Ok(mut cached) = DIR.write() {
+            *cached = Some(dir.clone());
+        }
+        dir
+    });
+    fs::create_dir_all(&dir).ok();
+    dir
+}
+
+/// Points settings at `dir` and resets them to the defaults; `None` goes
+/// back to looking the directory up.
+#[cfg(test)]
+pub(crate) fn use_dir(dir: Option<PathBuf>) {
+    if let Ok(mut cached) = DIR.write() {
+        *cached = dir;
+    }
+    if let Ok(mut settings) = cell().lock() {
+        *settings = Settings::default();
+    }
+}
+
+fn settings_path() -> PathBuf {
+    settings_dir().join("settings.json")
+}
+
+fn load() -> Settings {
+    match fs::read_to_string(settings_path()) {
+        Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+            eprintln!("failed to parse settings.json, using defaults: {}", e);
+            Settings::default()
+        }),
+        Err(_) => Settings::default(),
+    }
+}
+
+fn cell() -> &'static Mutex<Settings> {
+    SETTINGS.get_or_init(|| Mutex::new(load()))
+}
+
+pub fn current() -> Settings {
+    cell().lock().map(|s| s.clone()).unwrap_or_default()
+}
+
+/// Applies `f` to the current settings and persists the result.
+pub fn update(f: impl FnOnce(&mut Settings)) -> Result<Settings, String> {
+    let mut settings = cell().lock().map_err(|e| e.to_string())?;
+    let mut next = settings.clone();
+    f(&mut next);
+    let data = serde_json::to_string_pretty(&next).map_err(|e| e.to_string())?;
+    write_atomic(&settings_path(), data.as_bytes())
+        .map_err(|e| format!("Failed to save settings: {}", e))?;
+    *settings = next.clone();
+    Ok(next)
+}
@@ -1,41 +1,733 @@
+pub mod archive;
+pub mod attachment;
+pub mod backup;
+pub mod crypto;
+pub mod history;
+pub mod image;
+pub mod import;
+mod journal;
+mod location;
+mod markdown;
+mod media;
+mod migrations;
+mod repository;
+mod sqlite;
+mod workspace;
+mod writer;
+
+use location::storage_dir;
+pub use location::{
+    begin_move as begin_storage_move, data_dir_info, portable_dir, DataDirInfo, DataDirSource,
+};
+#[cfg(test)]
+pub(crate) use location::{test_dir, TestDir};
+pub use markdown::MarkdownRepository;
+pub use media::{
+    collect_garbage as collect_media_garbage, is_content_name, media_references,
+    migrate_to_content_names as migrate_media, resolve as resolve_media,
+    variant_path as media_variant_path, LookupError as MediaLookupError, MediaGcReport,
+    VariantFile as MediaVariant,
+};
+pub use repository::{JsonRepository, NoteRepository};
+pub use sqlite::SqliteRepository;
+use workspace::all_workspace_dirs;
+pub use workspace::{
+    active_workspace, create_workspace, list_workspaces, validate_name, workspace_dir,
+};
+pub use writer::NoteWriter;
+
 use crate::notes::Note;
+use crate::settings::{self, StorageBackend};
+use migrations::CURRENT_VERSION;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
 
-fn storage_dir() -> PathBuf {
-    let dir = dirs::data_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("com.stickynotes.app");
-    fs::create_dir_all(&dir).ok();
-    dir
+// Saves happen on every edit, so only snapshot a new generation this often.
+const GENERATION_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn storage_path() -> PathBuf {
+    workspace_dir().join("notes.json")
+}
+
+fn database_path() -> PathBuf {
+    workspace_dir().join("notes.db")
+}
+
+fn markdown_dir() -> PathBuf {
+    workspace_dir().join("notes")
 }
 
-fn storage_path() -> PathBuf {
-    storage_dir().join("notes.json")
+fn generations_dir() -> PathBuf {
+    workspace_dir().join("generations")
+}
+
+fn corrupt_dir() -> PathBuf {
+    workspace_dir().join("corrupt")
 }
 
 pub fn media_dir() -> PathBuf {
-    let dir = storage_dir().join("media");
+    let dir = workspace_dir().join("media");
     fs::create_dir_all(&dir).ok();
     dir
 }
 
-pub fn load_notes() -> HashMap<String, Note> {
+/// Hash of each notes.json as this process last read or wrote it, so our own
+/// writes can be told apart from changes made by other programs.
+static KNOWN_SNAPSHOTS: OnceLock<Mutex<HashMap<PathBuf, u64>>> = OnceLock::new();
+
+fn content_hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn remember_snapshot(path: &Path, data: &[u8]) {
+    let known = KNOWN_SNAPSHOTS.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Ok(mut known) = known.lock() {
+        known.insert(path.to_path_buf(), content_hash(data));
+    }
+}
+
+/// Whether notes.json now differs from what this process last read or wrote.
+/// A missing file is not treated as a change.
+pub fn snapshot_changed() -> bool {
+    let path = storage_path();
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(_) => return false,
+    };
+    let known = KNOWN_SNAPSHOTS.get_or_init(|| Mutex::new(HashMap::new()));
+    match known.lock() {
+        Ok(known) => known.get(&path) != Some(&content_hash(&data)),
+        Err(_) => false,
+    }
+}
+
+#[derive(Clone)]
+enum SnapshotHold {
+    /// The damaged file could not be moved aside. Edits still go to the
+    /// journal, but notes.json is never rewritten.
+    Damaged(String),
+    /// The file was written by a newer build. Notes are shown read-only and
+    /// nothing is written at all, so that build finds its file untouched.
+    Newer(String),
+}
+
+/// Set while notes.json must not be overwritten. Cleared whenever the
+/// snapshot is read again.
+static SNAPSHOT_HOLD: Mutex<Option<SnapshotHold>> = Mutex::new(None);
+
+fn set_snapshot_hold(reason: Option<SnapshotHold>) {
+    if let Ok(mut hold) = SNAPSHOT_HOLD.lock() {
+        *hold = reason;
+    }
+}
+
+/// Why notes.json is currently protected from saves, if it is.
+pub fn snapshot_hold() -> Option<String> {
+    match SNAPSHOT_HOLD.lock().ok()?.clone()? {
+        SnapshotHold::Damaged(reason) | SnapshotHold::Newer(reason) => Some(reason),
+    }
+}
+
+/// Whether notes were loaded from a file this build cannot write.
+pub fn snapshot_read_only() -> bool {
+    matches!(
+        SNAPSHOT_HOLD.lock().ok().and_then(|hold| hold.clone()),
+        Some(SnapshotHold::Newer(_))
+    )
+}
+
+/// Writes `data` to a sibling temp file, fsyncs it and renames it over `path`,
+/// so a crash mid-write leaves either the old file or the new one.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("data");
+    let tmp_path = dir.join(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+
+    let result = (|| {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp_path, path)
+    })();
+
+    if result.is_err() {
+        fs::remove_file(&tmp_path).ok();
+    }
+    result?;
+    sync_dir(dir);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) {
+    if let Ok(handle) = File::open(dir) {
+        handle.sync_all().ok();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) {}
+
+fn list_generations(dir: &Path) -> Vec<PathBuf> {
+    let mut generations: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| {
+                    p.file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|n| n.starts_with("notes-") && n.ends_with(".json"))
+                        .unwrap_or(false)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    // Timestamps in the file names sort chronologically.
+    generations.sort();
+    generations
+}
+
+/// Copies the current notes.json into `generations/` before it is replaced,
+/// keeping at most `keep` copies.
+fn rotate_generations(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 || !path.exists() {
+        return Ok(());
+    }
+
+    let dir = generations_dir();
+    fs::create_dir_all(&dir)?;
+    let mut generations = list_generations(&dir);
+
+    let recent = generations
+        .last()
+        .and_then(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .map(|age| age < GENERATION_INTERVAL)
+        .unwrap_or(false);
+
+    if !recent {
+        let name = format!(
+            "notes-{}.json",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+        );
+        let target = dir.join(name);
+        fs::copy(path, &target)?;
+        generations.push(target);
+    }
+
+    while generations.len() > keep {
+        let oldest = generations.remove(0);
+        fs::remove_file(&oldest).ok();
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LostNote {
+    pub index: usize,
+    pub id: Option<String>,
+    pub error: String,
+}
+
+/// Describes what happened when notes.json could not be parsed as a whole.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveryReport {
+    pub corrupt_file: String,
+    pub error: String,
+    pub recovered: usize,
+    pub lost: Vec<LostNote>,
+    /// True when the file ended before the notes array was closed, so any
+    /// entries after the last complete one are gone without a trace.
+    pub truncated: bool,
+}
+
+pub struct LoadedNotes {
+    pub notes: HashMap<String, Note>,
+    pub recovery: Option<RecoveryReport>,
+}
+
+#[derive(Serialize)]
+struct StoreFileRef<'a> {
+    version: u64,
+    notes: Vec<&'a Note>,
+}
+
+#[derive(Deserialize)]
+struct StoreFile {
+    notes: Vec<Note>,
+}
+
+/// Parses a notes.json document of any known version.
+fn parse_store(data: &str) -> Result<Vec<Note>, String> {
+    let value: Value = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    let value = migrations::migrate(value)?;
+    let store: StoreFile = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    Ok(store.notes)
+}
+
+/// Finds the notes array inside a possibly damaged document, along with the
+/// format version it claims to be.
+fn locate_notes_array(data: &str) -> (&str, u64) {
+    let trimmed = data.trim_start();
+    if trimmed.starts_with('[') {
+        return (trimmed, 0);
+    }
+
+    let version = data
+        .find("\"version\"")
+        .map(|i| &data[i + "\"version\"".len()..])
+        .map(|rest| rest.trim_start().trim_start_matches(':').trim_start())
+        .map(|rest| {
+            rest.chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+        })
+        .and_then(|digits| digits.parse().ok())
+        .unwrap_or(CURRENT_VERSION);
+    let array = data
+        .find("\"notes\"")
+        .and_then(|i| data[i..].find('[').map(|j| &data[i + j..]))
+        .unwrap_or("");
+    (array, version)
+}
+
+/// Pulls complete JSON values out of a top-level array one at a time, stopping
+/// at the first one that does not parse. Returns the values and whether the
+/// array was cut short.
+fn salvage_array(data: &str) -> (Vec<Value>, bool) {
+    let mut values = Vec::new();
+    let mut rest = match data.trim_start().strip_prefix('[') {
+        Some(rest) => rest,
+        None => return (values, true),
+    };
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.starts_with(']') {
+            return (values, false);
+        }
+        let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(value)) => {
+                values.push(value);
+                rest = &rest[stream.byte_offset()..];
+            }
+            _ => return (values, true),
+        }
+    }
+}
+
+fn recover_notes(data: &str, error: String, corrupt_file: String) -> LoadedNotes {
+    let (array, version) = locate_notes_array(data);
+    let (values, truncated) = salvage_array(array);
+    let count = values.len();
+
+    // Bring the salvaged entries up to the current format. Files from a newer
+    // build are read as-is; unknown fields are ignored by serde.
+    let document = if version == 0 {
+        Value::Array(values)
+    } else {
+        json!({ "version": version, "notes": values })
+    };
+    let migrated = if version > CURRENT_VERSION {
+        Ok(document)
+    } else {
+        migrations::migrate(document)
+    };
+
+    let mut notes = HashMap::new();
+    let mut lost = Vec::new();
+    match migrated {
+        Ok(Value::Object(mut map)) => {
+            let entries = match map.remove("notes") {
+                Some(Value::Array(entries)) => entries,
+                _ => Vec::new(),
+            };
+            for (index, value) in entries.into_iter().enumerate() {
+                let id = value.get("id").and_then(|v| v.as_str()).map(String::from);
+                match serde_json::from_value::<Note>(value) {
+                    Ok(note) => {
+                        notes.insert(note.id.clone(), note);
+                    }
+                    Err(e) => lost.push(LostNote {
+                        index,
+                        id,
+                        error: e.to_string(),
+                    }),
+                }
+            }
+        }
+        Ok(_) => {}
+        Err(e) => {
+            lost = (0..count)
+                .map(|index| LostNote {
+                    index,
+                    id: None,
+                    error: e.clone(),
+                })
+                .collect();
+        }
+    }
+
+    LoadedNotes {
+        recovery: Some(RecoveryReport {
+            corrupt_file,
+            error,
+            recovered: notes.len(),
+            lost,
+            truncated,
+        }),
+        notes,
+    }
+}
+
+/// Moves an unreadable notes.json aside so the next save cannot overwrite it.
+fn quarantine_corrupt(path: &Path) -> io::Result<PathBuf> {
+    let dir = corrupt_dir();
+    fs::create_dir_all(&dir)?;
+    let target = dir.join(format!(
+        "notes-{}.json",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    ));
+    fs::rename(path, &target).or_else(|_| fs::copy(path, &target).map(|_| ()))?;
+    Ok(target)
+}
+
+/// Reads the notes.json snapshot and replays the change journal on top.
+pub fn load_notes() -> LoadedNotes {
+    let mut loaded = load_snapshot();
+    let replayed = journal::replay(&mut loaded.notes);
+    if replayed > 0 {
+        eprintln!("replayed {} journal entries over notes.json", replayed);
+    }
+    loaded
+}
+
+/// Reads notes.json after another program replaced it, merging the journal
+/// in note by note so that edits made elsewhere are not overwritten.
+pub fn reload_notes() -> LoadedNotes {
+    let mut loaded = load_snapshot();
+    let merged = journal::merge(&mut loaded.notes);
+    if merged > 0 {
+        eprintln!("merged {} journal entries into changed notes.json", merged);
+    }
+    loaded
+}
+
+fn load_snapshot() -> LoadedNotes {
+    set_snapshot_hold(None);
     let path = storage_path();
-    match fs::read_to_string(&path) {
-        Ok(data) => {
-            let notes: Vec<Note> = serde_json::from_str(&data).unwrap_or_default();
-            notes.into_iter().map(|n| (n.id.clone(), n)).collect()
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return LoadedNotes {
+                notes: HashMap::new(),
+                recovery: None,
+            }
+        }
+    };
+    remember_snapshot(&path, &bytes);
+
+    // A sealed file that fails to decrypt has been damaged or tampered with;
+    // nothing in it can be salvaged, but it is still moved aside below.
+    let (data, parsed) = match crypto::open(&bytes) {
+        Ok(plain) => {
+            let data = String::from_utf8_lossy(&plain).into_owned();
+            let parsed = parse_store(&data);
+            (data, parsed)
+        }
+        Err(e) => (String::new(), Err(e)),
+    };
+    match parsed {
+        Ok(notes) => LoadedNotes {
+            notes: notes.into_iter().map(|n| (n.id.clone(), n)).collect(),
+            recovery: None,
+        },
+        // A newer format is not damage: leave the file where it is and show
+        // what can be read without writing anything back.
+        Err(e) if locate_notes_array(&data).1 > CURRENT_VERSION => {
+            eprintln!("{}", e);
+            set_snapshot_hold(Some(SnapshotHold::Newer(format!(
+                "{}; notes are read-only and changes will not be saved",
+                e
+            ))));
+            let loaded = recover_notes(&data, e, path.to_string_lossy().into_owned());
+            LoadedNotes {
+                notes: loaded.notes,
+                recovery: None,
+            }
+        }
+        Err(e) => {
+            eprintln!("notes.json is corrupt, recovering what we can: {}", e);
+            let corrupt_file = match quarantine_corrupt(&path) {
+                Ok(target) => target.to_string_lossy().into_owned(),
+                Err(move_err) => {
+                    eprintln!("failed to move corrupt notes.json aside: {}", move_err);
+                    // The only copy of the damaged file is still in place, so
+                    // edits stay in the journal until it can be moved.
+                    set_snapshot_hold(Some(SnapshotHold::Damaged(format!(
+                        "notes.json is damaged and could not be moved aside ({}); changes are kept in the journal but notes.json will not be overwritten",
+                        move_err
+                    ))));
+                    path.to_string_lossy().into_owned()
+                }
+            };
+            recover_notes(&data, e, corrupt_file)
         }
-        Err(_) => HashMap::new(),
     }
 }
 
-pub fn save_notes(notes: &HashMap<String, Note>) {
+/// Writes a full snapshot to notes.json, which also compacts the journal.
+pub fn save_notes(notes: &HashMap<String, Note>) -> Result<(), String> {
+    if let Some(reason) = snapshot_hold() {
+        return Err(reason);
+    }
     let path = storage_path();
-    let notes_vec: Vec<&Note> = notes.values().collect();
-    if let Ok(data) = serde_json::to_string_pretty(&notes_vec) {
-        fs::write(&path, data).ok();
+    let store = StoreFileRef {
+        version: CURRENT_VERSION,
+        notes: notes.values().collect(),
+    };
+    let data = serde_json::to_string_pretty(&store).map_err(|e| e.to_string())?;
+
+    if let Err(e) = rotate_generations(&path, settings::current().backup_generations) {
+        eprintln!("failed to rotate notes.json generations: {}", e);
+    }
+    let data = crypto::seal(data.as_bytes())?;
+    // Recorded before the rename so the watcher never mistakes it for an
+    // outside change.
+    remember_snapshot(&path, &data);
+    write_atomic(&path, &data).map_err(|e| format!("Failed to save notes: {}", e))?;
+    // Everything in the journal is now part of the snapshot.
+    journal::clear().map_err(|e| format!("Failed to clear notes journal: {}", e))
+}
+
+/// Overwrites every copy of `note` outside the active backend: older
+/// generations of notes.json, stores left behind by other backends and
+/// backups. Used once a note is locked, so its plaintext does not stay on
+/// disk.
+pub fn scrub_note(note: &Note) -> Result<(), String> {
+    // Nothing may be written over a file from a newer build.
+    if snapshot_read_only() {
+        return Ok(());
+    }
+    let backend = settings::current().storage_backend;
+    if backend != StorageBackend::Json && storage_path().exists() {
+        let mut loaded = load_notes();
+        if loaded.notes.contains_key(&note.id) {
+            loaded.notes.insert(note.id.clone(), note.clone());
+            save_notes(&loaded.notes)?;
+        }
+    }
+    if backend != StorageBackend::Sqlite && database_path().exists() {
+        let mut repo = SqliteRepository::open(&database_path())?;
+        if repo.get(&note.id)?.is_some() {
+            repo.put(note)?;
+            repo.compact()?;
+        }
+    }
+    if backend != StorageBackend::Markdown && markdown_dir().exists() {
+        let mut repo = MarkdownRepository::open(&markdown_dir())?;
+        if repo.get(&note.id)?.is_some() {
+            repo.put(note)?;
+        }
+    }
+    for path in list_generations(&generations_dir()) {
+        scrub_generation(&path, note)
+            .map_err(|e| format!("Failed to update {}: {}", path.display(), e))?;
+    }
+    backup::scrub_note(&workspace_dir(), note)
+}
+
+fn scrub_generation(path: &Path, note: &Note) -> Result<(), String> {
+    let parsed = fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| crypto::open(&bytes))
+        .and_then(|plain| parse_store(&String::from_utf8_lossy(&plain)));
+    let mut notes = match parsed {
+        Ok(notes) => notes,
+        // There is no telling whether it holds the note, so it goes.
+        Err(_) => return fs::remove_file(path).map_err(|e| e.to_string()),
+    };
+    let Some(old) = notes.iter_mut().find(|n| n.id == note.id) else {
+        return Ok(());
+    };
+    *old = note.clone();
+    let store = StoreFileRef {
+        version: CURRENT_VERSION,
+        notes: notes.iter().collect(),
+    };
+    let data = serde_json::to_string_pretty(&store).map_err(|e| e.to_string())?;
+    write_atomic(path, &crypto::seal(data.as_bytes())?).map_err(|e| e.to_string())
+}
+
+/// Fills a newly created backend from notes.json, if there is one.
+fn seed_from_json(repo: &mut dyn NoteRepository) -> Result<Option<RecoveryReport>, String> {
+    if !storage_path().exists() {
+        return Ok(None);
+    }
+    let legacy = load_notes();
+    // Copying would drop whatever the newer format added.
+    if snapshot_read_only() {
+        return Err(snapshot_hold().unwrap_or_default());
+    }
+    let notes: Vec<Note> = legacy.notes.into_values().collect();
+    repo.replace_all(&notes)?;
+    Ok(legacy.recovery)
+}
+
+fn loaded_from(
+    repo: &dyn NoteRepository,
+    recovery: Option<RecoveryReport>,
+) -> Result<LoadedNotes, String> {
+    let notes = repo
+        .list()?
+        .into_iter()
+        .map(|n| (n.id.clone(), n))
+        .collect();
+    Ok(LoadedNotes { notes, recovery })
+}
+
+/// Opens the given backend and reads every note from it. The first time the
+/// SQLite database or the Markdown directory is created it is seeded from
+/// notes.json, so switching the setting by hand does not start from empty.
+pub fn open_repository(
+    backend: StorageBackend,
+) -> Result<(Box<dyn NoteRepository>, LoadedNotes), String> {
+    if crypto::is_locked() {
+        return Err("Notes are locked".to_string());
+    }
+    match backend {
+        StorageBackend::Json => {
+            let (repo, loaded) = JsonRepository::open();
+            Ok((Box::new(repo), loaded))
+        }
+        StorageBackend::Sqlite => {
+            let path = database_path();
+            let fresh = !path.exists();
+            let mut repo = SqliteRepository::open(&path)?;
+            let recovery = if fresh {
+                seed_from_json(&mut repo)?
+            } else {
+                None
+            };
+            let loaded = loaded_from(&repo, recovery)?;
+            Ok((Box::new(repo), loaded))
+        }
+        StorageBackend::Markdown => {
+            let dir = markdown_dir();
+            let fresh = !dir.exists();
+            let mut repo = MarkdownRepository::open(&dir)?;
+            let recovery = if fresh {
+                seed_from_json(&mut repo)?
+            } else {
+                None
+            };
+            let loaded = loaded_from(&repo, recovery)?;
+            Ok((Box::new(repo), loaded))
+        }
+    }
+}
+
+/// Re-encrypts everything in the data directory, in every workspace, from
+/// `old` to `new` (either may be `None` for plaintext), and renames media to
+/// match. Files that are already in the target state are skipped, so an
+/// interrupted run can be repeated. The writer must be closed while this
+/// runs.
+pub fn reseal_data_dir(
+    old: Option<&crypto::Key>,
+    new: Option<&crypto::Key>,
+) -> Result<usize, String> {
+    let count = reseal_tree(&storage_dir(), old, new)?;
+    for dir in all_workspace_dirs() {
+        media::rename_for_key(&dir, old, new)?;
+    }
+    Ok(count)
+}
+
+fn reseal_tree(
+    dir: &Path,
+    old: Option<&crypto::Key>,
+    new: Option<&crypto::Key>,
+) -> Result<usize, String> {
+    let entries = fs::read_dir(dir).map_err(|e| e.to_string())?;
+    let mut count = 0;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if path.is_dir() {
+            count += reseal_tree(&path, old, new)?;
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Settings hold the KDF parameters and must stay readable; SQLite's
+        // side files and our own temp files are rewritten by their owners.
+        if name == "settings.json"
+            || name.starts_with('.')
+            || name.ends_with(".db-wal")
+            || name.ends_with(".db-shm")
+        {
+            continue;
+        }
+        let resealed = if name.ends_with(".db") {
+            sqlite::reseal(&path, old, new).map(|_| true)
+        } else if name.ends_with(".journal") {
+            reseal_lines(&path, old, new)
+        } else {
+            reseal_file(&path, old, new)
+        };
+        if resealed.map_err(|e| format!("Failed to re-encrypt {}: {}", path.display(), e))? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn reseal_file(
+    path: &Path,
+    old: Option<&crypto::Key>,
+    new: Option<&crypto::Key>,
+) -> Result<bool, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let done = match new {
+        Some(_) => crypto::is_sealed(&data) && crypto::open_with(new, &data).is_ok(),
+        None => !crypto::is_sealed(&data),
+    };
+    if done {
+        return Ok(false);
+    }
+    let plain = crypto::open_with(old, &data)?;
+    let sealed = crypto::seal_with(new, &plain)?;
+    if path.file_name().and_then(|n| n.to_str()) == Some("notes.json") {
+        remember_snapshot(path, &sealed);
+    }
+    write_atomic(path, &sealed).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+fn reseal_lines(
+    path: &Path,
+    old: Option<&crypto::Key>,
+    new: Option<&crypto::Key>,
+) -> Result<bool, String> {
+    let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut out = String::with_capacity(data.len());
+    for line in data.lines().filter(|l| !l.trim().is_empty()) {
+        let plain = crypto::open_text_with(old, line)
+            .or_else(|e| crypto::open_text_with(new, line).map_err(|_| e))?;
+        out.push_str(&crypto::seal_text_with(new, &plain)?);
+        out.push('\n');
     }
+    write_atomic(path, out.as_bytes()).map_err(|e| e.to_string())?;
+    Ok(true)
 }
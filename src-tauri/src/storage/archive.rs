@@ -0,0 +1,356 @@
+//! Moves a board to another machine as one zip file: the notes as a
+//! notes.json document, every media file they refer to under `media/` and a
+//! manifest listing those files.
+//!
+//! Unlike backups, nothing in an archive is sealed with the store key, since
+//! the other machine has its own; locked notes stay sealed with their note
+//! password. On import each media file is checked and stored like a dropped
+//! one, which can give it a different name, so references are rewritten to
+//! wherever it ended up.
+
+use super::backup::referenced_media;
+use super::image::ImageFormat;
+use super::import::{self, Imported};
+use super::migrations::CURRENT_VERSION;
+use super::{attachment, crypto, media, parse_store, StoreFileRef};
+use crate::notes::Note;
+use crate::settings;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const FORMAT: &str = "stickynotes-archive";
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+const NOTES_FILE: &str = "notes.json";
+const MEDIA_PREFIX: &str = "media/";
+/// Largest notes.json an import will read, so a crafted archive cannot
+/// inflate into all of memory.
+const MAX_NOTES_BYTES: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Adds the archived notes next to the current ones.
+    Merge,
+    /// Makes the archived notes the only ones, after a backup of the
+    /// current notes.
+    Replace,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    version: u32,
+    created_at: String,
+    notes: usize,
+    media: Vec<ArchivedMedia>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedMedia {
+    /// Name the notes refer to it by, and its entry under `media/`.
+    file: String,
+    /// File name an attachment had when it was attached.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportSummary {
+    pub path: String,
+    pub notes: usize,
+    pub media: usize,
+    /// Media files that were referenced but could not be found.
+    pub missing_media: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportSummary {
+    pub mode: ImportMode,
+    /// Notes added, or in replace mode the notes there are now.
+    pub notes: usize,
+    /// Archived notes already here unchanged, which were left out.
+    pub skipped: usize,
+    /// Archived notes given a new id because theirs was taken.
+    pub renamed: usize,
+    pub media: usize,
+    /// Media files that were missing from the archive or failed the checks a
+    /// dropped file goes through.
+    pub rejected_media: Vec<String>,
+}
+
+/// Notes and media read from an archive, with the media already stored.
+pub struct ArchiveContents {
+    pub notes: Vec<Note>,
+    pub media: usize,
+    pub rejected_media: Vec<String>,
+}
+
+/// Writes `notes` and the media they use to a zip at `path`. The zip is
+/// written next to it under a temporary name and renamed into place.
+pub fn export(path: &Path, notes: &[Note]) -> Result<ExportSummary, String> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = Path::new(&tmp);
+    let result = (|| -> Result<ExportSummary, String> {
+        let file = File::create(tmp).map_err(|e| e.to_string())?;
+        let mut zip = ZipWriter::new(file);
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        let mut referenced: Vec<String> = referenced_media(notes).into_iter().collect();
+        referenced.sort();
+        let mut archived = Vec::new();
+        let mut missing_media = 0;
+        for name in referenced {
+            let path = match media::resolve(&name) {
+                Ok(path) => path,
+                Err(_) => {
+                    missing_media += 1;
+                    continue;
+                }
+            };
+            let bytes = crypto::open(&fs::read(&path).map_err(|e| e.to_string())?)?;
+            zip.start_file(format!("{}{}", MEDIA_PREFIX, name), options)
+                .map_err(|e| e.to_string())?;
+            zip.write_all(&bytes).map_err(|e| e.to_string())?;
+            let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+            let name_attached = match ImageFormat::from_ext(ext) {
+                Some(_) => None,
+                None => attachment::info(&name).ok().map(|a| a.name),
+            };
+            archived.push(ArchivedMedia {
+                file: name,
+                name: name_attached,
+            });
+        }
+
+        let document = StoreFileRef {
+            version: CURRENT_VERSION,
+            notes: notes.iter().collect(),
+        };
+        zip.start_file(NOTES_FILE, options)
+            .map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(&mut zip, &document).map_err(|e| e.to_string())?;
+
+        let summary = ExportSummary {
+            path: path.to_string_lossy().into_owned(),
+            notes: notes.len(),
+            media: archived.len(),
+            missing_media,
+        };
+        let manifest = Manifest {
+            format: FORMAT.to_string(),
+            version: FORMAT_VERSION,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            notes: notes.len(),
+            media: archived,
+        };
+        zip.start_file(MANIFEST_FILE, options)
+            .map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(&mut zip, &manifest).map_err(|e| e.to_string())?;
+        zip.finish()
+            .and_then(|file| file.sync_all().map_err(Into::into))
+            .map_err(|e| e.to_string())?;
+        fs::rename(tmp, path).map_err(|e| e.to_string())?;
+        Ok(summary)
+    })();
+    if result.is_err() {
+        fs::remove_file(tmp).ok();
+    }
+    result.map_err(|e| format!("Failed to write archive: {}", e))
+}
+
+/// Reads entry `name` whole, refusing it if it unpacks to more than `max`.
+fn read_entry(zip: &mut ZipArchive<File>, name: &str, max: u64) -> Result<Vec<u8>, String> {
+    let entry = zip.by_name(name).map_err(|e| format!("{}: {}", name, e))?;
+    if entry.size() > max {
+        return Err(format!("{} is larger than {} bytes", name, max));
+    }
+    let mut bytes = Vec::new();
+    entry
+        .take(max + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("{}: {}", name, e))?;
+    if bytes.len() as u64 > max {
+        return Err(format!("{} is larger than {} bytes", name, max));
+    }
+    Ok(bytes)
+}
+
+/// Stores an archived media file the way a dropped one is stored and
+/// returns the name it got.
+fn store_media(
+    zip: &mut ZipArchive<File>,
+    item: &ArchivedMedia,
+    max: u64,
+) -> Result<String, String> {
+    let bytes = read_entry(zip, &format!("{}{}", MEDIA_PREFIX, item.file), max)?;
+    // Attachments keep their original name if it has the extension the file
+    // was stored with.
+    let ext = |name: &str| attachment::extension(name).ok();
+    let name = item
+        .name
+        .as_deref()
+        .filter(|name| ext(name).is_some() && ext(name) == ext(&item.file))
+        .unwrap_or(&item.file);
+    match import::import_bytes(&bytes, name, "").map_err(|e| e.to_string())? {
+        Imported::Image { file } => Ok(file),
+        Imported::Attachment(attachment) => Ok(attachment.file),
+    }
+}
+
+fn valid_note_id(id: &str) -> bool {
+    !id.is_empty() && !id.starts_with('.') && !id.contains(['/', '\\'])
+}
+
+/// Reads the archive at `path` and stores its media. The notes come back
+/// with their media references pointing at the stored files; trashed notes
+/// are left out, and notes with a missing or repeated id get a new one.
+pub fn import(path: &Path) -> Result<ArchiveContents, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open archive: {}", e))?;
+    let mut zip = ZipArchive::new(file).map_err(|e| format!("Not a zip archive: {}", e))?;
+
+    let manifest: Manifest =
+        serde_json::from_slice(&read_entry(&mut zip, MANIFEST_FILE, MAX_NOTES_BYTES)?)
+            .map_err(|e| format!("Invalid archive manifest: {}", e))?;
+    if manifest.format != FORMAT {
+        return Err("Not a Sticky Notes archive".to_string());
+    }
+    if manifest.version > FORMAT_VERSION {
+        return Err(format!(
+            "Archive version {} is newer than this app supports",
+            manifest.version
+        ));
+    }
+    let data = read_entry(&mut zip, NOTES_FILE, MAX_NOTES_BYTES)?;
+    let data = String::from_utf8(data).map_err(|e| e.to_string())?;
+    let mut notes = parse_store(&data)?;
+
+    let settings = settings::current();
+    let max = settings.max_image_bytes.max(settings.max_attachment_bytes);
+    let mut renamed = HashMap::new();
+    let mut stored_media = 0;
+    let mut rejected_media = Vec::new();
+    for item in manifest.media {
+        if !media::is_plain_name(&item.file) {
+            rejected_media.push(item.file);
+            continue;
+        }
+        match store_media(&mut zip, &item, max) {
+            Ok(file) => {
+                if file != item.file {
+                    renamed.insert(item.file, file);
+                }
+                stored_media += 1;
+            }
+            Err(e) => {
+                eprintln!("skipping archived media {}: {}", item.file, e);
+                rejected_media.push(item.file);
+            }
+        }
+    }
+
+    // Sealed content cannot be rewritten, so locked notes find their media
+    // through aliases instead.
+    let mut aliases = HashMap::new();
+    let mut seen = HashSet::new();
+    notes.retain(|note| note.deleted_at.is_none());
+    for note in &mut notes {
+        if !valid_note_id(&note.id) || !seen.insert(note.id.clone()) {
+            note.id = uuid::Uuid::new_v4().to_string();
+            seen.insert(note.id.clone());
+        }
+        note.content = media::rewrite_references(&note.content, |name| renamed.get(name).cloned());
+        if let Some(lock) = &mut note.lock {
+            for name in &mut lock.media {
+                if let Some(new) = renamed.get(name) {
+                    aliases.insert(name.clone(), new.clone());
+                    *name = new.clone();
+                }
+            }
+        }
+    }
+    media::add_aliases(&aliases)?;
+
+    Ok(ArchiveContents {
+        notes,
+        media: stored_media,
+        rejected_media,
+    })
+}
+
+/// Archived notes fitted in next to `existing` ones, for merge mode.
+pub struct Merged {
+    pub added: Vec<Note>,
+    pub skipped: usize,
+    pub renamed: usize,
+}
+
+/// Splits archived `notes` into those to add and those already here. A note
+/// whose id is taken by a different note is kept too, under a new id, so
+/// neither side is lost.
+pub fn merge(notes: Vec<Note>, existing: &[&HashMap<String, Note>]) -> Merged {
+    let same = |a: &Note, b: &Note| {
+        a.content == b.content
+            && a.updated_at == b.updated_at
+            && a.lock.as_ref().map(|l| &l.content) == b.lock.as_ref().map(|l| &l.content)
+    };
+    let mut merged = Merged {
+        added: Vec::new(),
+        skipped: 0,
+        renamed: 0,
+    };
+    for mut note in notes {
+        match existing.iter().find_map(|notes| notes.get(&note.id)) {
+            Some(current) if same(current, &note) => merged.skipped += 1,
+            Some(_) => {
+                note.id = uuid::Uuid::new_v4().to_string();
+                merged.renamed += 1;
+                merged.added.push(note);
+            }
+            None => merged.added.push(note),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, content: &str) -> Note {
+        let mut note = Note::new();
+        note.id = id.to_string();
+        note.content = content.to_string();
+        note
+    }
+
+    #[test]
+    fn merging_keeps_both_sides_of_a_collision() {
+        let here = note("a", "<p>mine</p>");
+        let notes = HashMap::from([("a".to_string(), here.clone())]);
+        let trash = HashMap::from([("b".to_string(), note("b", "<p>old</p>"))]);
+
+        let mut changed = note("a", "<p>theirs</p>");
+        changed.updated_at = here.updated_at.clone();
+        let archived = vec![
+            here,
+            changed,
+            note("b", "<p>other</p>"),
+            note("c", "<p>new</p>"),
+        ];
+        let merged = merge(archived, &[&notes, &trash]);
+
+        assert_eq!((merged.skipped, merged.renamed), (1, 2));
+        let ids: Vec<&str> = merged.added.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids.len(), 3);
+        assert!(ids[0] != "a" && ids[1] != "b");
+        assert_eq!(ids[2], "c");
+        assert_eq!(merged.added[0].content, "<p>theirs</p>");
+    }
+}
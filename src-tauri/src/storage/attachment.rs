@@ -0,0 +1,297 @@
+//! Files attached to notes that are not images: documents, text, audio and
+//! archives.
+//!
+//! Attachments are stored next to images in `media/` under their content
+//! name, so the same GC, encryption and `media://` handling applies. The
+//! name the file had when it was attached and its size are kept in a
+//! sealed sidecar in `media/meta/`, since the stored name is just a hash.
+
+use super::media::{self, meta_dir};
+use super::{crypto, write_atomic};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tempfile::TempDir;
+
+/// How much of a file `content_matches` looks at when importing from disk.
+pub const HEAD_LEN: usize = 8 * 1024;
+
+/// Extensions accepted by `save_attachment` and the mime type each is
+/// served as.
+const TYPES: &[(&str, &str)] = &[
+    ("pdf", "application/pdf"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("json", "application/json"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("flac", "audio/flac"),
+    ("m4a", "audio/mp4"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+];
+
+/// What the frontend needs to draw an attachment chip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    /// Stored file name, as used in `http://media.localhost/<file>`.
+    pub file: String,
+    /// File name at the time it was attached.
+    pub name: String,
+    pub size: u64,
+    pub mime: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AttachmentError {
+    InvalidBase64 { message: String },
+    TooLarge { size: u64, max: u64 },
+    UnsupportedType { extension: String },
+    ContentMismatch { extension: String },
+    Storage { message: String },
+}
+
+impl fmt::Display for AttachmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBase64 { message } => write!(f, "Invalid base64: {}", message),
+            Self::TooLarge { size, max } => {
+                write!(f, "File is {} bytes, the limit is {}", size, max)
+            }
+            Self::UnsupportedType { extension } => {
+                write!(f, "Files of type .{} cannot be attached", extension)
+            }
+            Self::ContentMismatch { extension } => {
+                write!(
+                    f,
+                    "File content does not match its .{} extension",
+                    extension
+                )
+            }
+            Self::Storage { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for AttachmentError {
+    fn from(message: String) -> Self {
+        Self::Storage { message }
+    }
+}
+
+pub fn mime_for_ext(ext: &str) -> Option<&'static str> {
+    TYPES.iter().find(|(e, _)| *e == ext).map(|(_, mime)| *mime)
+}
+
+/// Checks the magic number of binary formats, and that text formats really
+/// are text. Only needs the first `HEAD_LEN` bytes.
+fn content_matches(ext: &str, bytes: &[u8]) -> bool {
+    match ext {
+        "pdf" => bytes.starts_with(b"%PDF-"),
+        // `bytes` may be just the start of the file, cut mid-character.
+        "txt" | "md" | "csv" | "json" => {
+            let valid = match std::str::from_utf8(bytes) {
+                Ok(_) => true,
+                Err(e) => e.error_len().is_none(),
+            };
+            valid && !bytes.contains(&0)
+        }
+        "mp3" => {
+            bytes.starts_with(b"ID3")
+                || (bytes.len() >= 2 && bytes[0] == 0xff && bytes[1] & 0xe0 == 0xe0)
+        }
+        "wav" => bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WAVE",
+        "ogg" | "oga" | "opus" => bytes.starts_with(b"OggS"),
+        "flac" => bytes.starts_with(b"fLaC"),
+        "m4a" => bytes.len() >= 8 && &bytes[4..8] == b"ftyp",
+        "zip" => bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06"),
+        "gz" => bytes.starts_with(&[0x1f, 0x8b]),
+        "tar" => bytes.len() >= 262 && &bytes[257..262] == b"ustar",
+        "7z" => bytes.starts_with(b"7z\xbc\xaf\x27\x1c"),
+        _ => false,
+    }
+}
+
+/// The lowercase extension of `name`, if attachments of that type are
+/// accepted.
+pub fn extension(name: &str) -> Result<String, AttachmentError> {
+    let ext = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    if mime_for_ext(&ext).is_none() {
+        return Err(AttachmentError::UnsupportedType { extension: ext });
+    }
+    Ok(ext)
+}
+
+fn meta_path(file: &str) -> std::path::PathBuf {
+    meta_dir().join(format!("{}.json", file))
+}
+
+/// Validates and stores `bytes` as an attachment called `name`.
+pub fn save(bytes: &[u8], name: &str, max: u64) -> Result<Attachment, AttachmentError> {
+    let size = bytes.len() as u64;
+    if size > max {
+        return Err(AttachmentError::TooLarge { size, max });
+    }
+    let ext = extension(name)?;
+    if !content_matches(&ext, bytes) {
+        return Err(AttachmentError::ContentMismatch { extension: ext });
+    }
+    let file = media::store(bytes, &ext)?;
+    let attachment = Attachment {
+        file,
+        name: display_name(name),
+        size,
+        mime: mime_for_ext(&ext).unwrap_or_default().to_string(),
+    };
+    write_meta(&attachment)?;
+    Ok(attachment)
+}
+
+/// Copies the file at `path` into media as an attachment, streaming it
+/// rather than loading it whole.
+pub fn import(path: &Path, max: u64) -> Result<Attachment, AttachmentError> {
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let ext = extension(name)?;
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", name, e))?;
+    let size = file.metadata().map_err(|e| e.to_string())?.len();
+    if size > max {
+        return Err(AttachmentError::TooLarge { size, max });
+    }
+    let mut head = Vec::with_capacity(HEAD_LEN);
+    (&mut file)
+        .take(HEAD_LEN as u64)
+        .read_to_end(&mut head)
+        .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    if !content_matches(&ext, &head) {
+        return Err(AttachmentError::ContentMismatch { extension: ext });
+    }
+    let attachment = Attachment {
+        file: media::store_stream(&head, file, &ext)?,
+        name: display_name(name),
+        size,
+        mime: mime_for_ext(&ext).unwrap_or_default().to_string(),
+    };
+    write_meta(&attachment)?;
+    Ok(attachment)
+}
+
+/// Just the file name, without whatever directory the caller had it in.
+fn display_name(name: &str) -> String {
+    name.rsplit(['/', '\\'])
+        .next()
+        .filter(|n| !n.is_empty() && *n != "." && *n != "..")
+        .unwrap_or("attachment")
+        .to_string()
+}
+
+/// Records `attachment`'s metadata unless the same content was attached
+/// before, in which case the first name is kept.
+pub fn write_meta(attachment: &Attachment) -> Result<(), String> {
+    let path = meta_path(&attachment.file);
+    if path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(meta_dir()).map_err(|e| e.to_string())?;
+    let data = serde_json::to_vec(attachment).map_err(|e| e.to_string())?;
+    write_atomic(&path, &crypto::seal(&data)?)
+        .map_err(|e| format!("Failed to write attachment metadata: {}", e))
+}
+
+/// Metadata for a stored attachment. Files attached before metadata was
+/// kept get their stored name and current size.
+pub fn info(file: &str) -> Result<Attachment, String> {
+    let path = media::resolve(file).map_err(|e| format!("Attachment not found: {:?}", e))?;
+    let file = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(file)
+        .to_string();
+    if let Ok(data) = fs::read(meta_path(&file)) {
+        let data = crypto::open(&data)?;
+        return serde_json::from_slice(&data).map_err(|e| e.to_string());
+    }
+    let bytes = crypto::open(&fs::read(&path).map_err(|e| e.to_string())?)?;
+    let ext = file.rsplit_once('.').map_or("", |(_, ext)| ext);
+    let mime = mime_for_ext(ext).unwrap_or("application/octet-stream");
+    Ok(Attachment {
+        name: file.clone(),
+        mime: mime.to_string(),
+        size: bytes.len() as u64,
+        file,
+    })
+}
+
+/// Private to this user (0700 on Unix) and removed by `remove_opened` on
+/// exit. Created on first use.
+static OPENED_DIR: Mutex<Option<TempDir>> = Mutex::new(None);
+
+/// Decrypts the attachment into a private temporary folder under its
+/// original name, so it can be handed to another application, and returns
+/// that path.
+pub fn export_for_opening(file: &str) -> Result<PathBuf, String> {
+    let attachment = info(file)?;
+    let path =
+        media::resolve(&attachment.file).map_err(|e| format!("Attachment not found: {:?}", e))?;
+    let bytes = crypto::open(&fs::read(&path).map_err(|e| e.to_string())?)?;
+
+    let mut opened = OPENED_DIR.lock().map_err(|e| e.to_string())?;
+    let root = match opened.as_ref() {
+        Some(dir) => dir.path().to_path_buf(),
+        None => {
+            let dir = tempfile::Builder::new()
+                .prefix("stickynotes-attachments-")
+                .tempdir()
+                .map_err(|e| format!("Failed to create a folder for attachments: {}", e))?;
+            let root = dir.path().to_path_buf();
+            *opened = Some(dir);
+            root
+        }
+    };
+    // One folder per stored file keeps equally named attachments apart.
+    let stem = attachment.file.split('.').next().unwrap_or_default();
+    let dir = root.join(stem);
+    fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+    let target = dir.join(display_name(&attachment.name));
+    // A copy from an earlier open is replaced. `create_new` refuses to
+    // follow anything that was put in its place.
+    match fs::remove_file(&target) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(format!("Failed to export attachment: {}", e))
+        }
+        _ => {}
+    }
+    File::options()
+        .write(true)
+        .create_new(true)
+        .open(&target)
+        .and_then(|mut out| out.write_all(&bytes))
+        .map_err(|e| format!("Failed to export attachment: {}", e))?;
+    Ok(target)
+}
+
+/// Deletes every attachment decrypted by `export_for_opening`.
+pub fn remove_opened() {
+    let dir = OPENED_DIR.lock().ok().and_then(|mut d| d.take());
+    if let Some(dir) = dir {
+        if let Err(e) = dir.close() {
+            eprintln!("failed to remove opened attachments: {}", e);
+        }
+    }
+}
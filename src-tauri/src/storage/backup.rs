@@ -0,0 +1,393 @@
+//! Daily and weekly snapshots of the active workspace, kept in `backups/`.
+//!
+//! Each backup is a folder holding the notes as a notes.json document
+//! (trashed notes included), the media files they refer to and a small
+//! manifest. Media is hard-linked where the file system allows it, so
+//! backups of unchanged images cost no extra space; files are only ever
+//! replaced by renaming, never rewritten in place, so a link keeps the
+//! bytes it was made with. Everything stays sealed as it was on disk.
+//!
+//! Locking a note rewrites it in every backup as it is once locked, so a
+//! restore cannot bring back its plaintext. Restoring an older backup then
+//! gives the locked note its current content.
+
+use super::media::{self, meta_dir};
+use super::migrations::CURRENT_VERSION;
+use super::workspace::workspace_dir;
+use super::{crypto, media_dir, parse_store, write_atomic, StoreFileRef};
+use crate::notes::Note;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MANIFEST_FILE: &str = "manifest.json";
+const NOTES_FILE: &str = "notes.json";
+/// Safety copies taken before a restore that are kept.
+const PRE_RESTORE_KEEP: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupKind {
+    Daily,
+    Weekly,
+    /// Taken automatically before `restore_backup` replaces the notes.
+    PreRestore,
+}
+
+impl BackupKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Weekly => "weekly",
+            Self::PreRestore => "pre_restore",
+        }
+    }
+
+    /// How old the newest backup of this kind may get before another is due.
+    fn interval(self) -> chrono::Duration {
+        match self {
+            Self::Daily => chrono::Duration::days(1),
+            Self::Weekly => chrono::Duration::weeks(1),
+            Self::PreRestore => chrono::Duration::zero(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupInfo {
+    /// Folder name under `backups/`, used to restore it.
+    pub id: String,
+    pub kind: BackupKind,
+    pub created_at: String,
+    pub notes: usize,
+    pub media: usize,
+    /// Media files that were referenced but could not be found.
+    #[serde(default)]
+    pub missing_media: usize,
+}
+
+fn backups_dir() -> PathBuf {
+    workspace_dir().join("backups")
+}
+
+fn backups_dir_in(workspace: &Path) -> PathBuf {
+    workspace.join("backups")
+}
+
+fn backup_dir(id: &str) -> Result<PathBuf, String> {
+    if id.is_empty() || id.starts_with('.') || id.contains(['/', '\\', ':']) {
+        return Err(format!("Invalid backup id {:?}", id));
+    }
+    let dir = backups_dir().join(id);
+    if !dir.join(MANIFEST_FILE).is_file() {
+        return Err(format!("Backup {} not found", id));
+    }
+    Ok(dir)
+}
+
+fn read_manifest(dir: &Path) -> Result<BackupInfo, String> {
+    let data = fs::read(dir.join(MANIFEST_FILE)).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&crypto::open(&data)?).map_err(|e| e.to_string())
+}
+
+/// Every media file `notes` refer to, including those inside locked notes.
+pub(super) fn referenced_media(notes: &[Note]) -> HashSet<String> {
+    let mut referenced = HashSet::new();
+    for note in notes {
+        referenced.extend(media::media_references(&note.content).map(String::from));
+        if let Some(lock) = &note.lock {
+            referenced.extend(lock.media.iter().cloned());
+        }
+    }
+    referenced
+}
+
+fn read_notes(dir: &Path) -> Result<Vec<Note>, String> {
+    let data = fs::read(dir.join(NOTES_FILE)).map_err(|e| e.to_string())?;
+    let data = String::from_utf8(crypto::open(&data)?).map_err(|e| e.to_string())?;
+    parse_store(&data)
+}
+
+fn write_notes(dir: &Path, notes: &[Note]) -> Result<(), String> {
+    let document = StoreFileRef {
+        version: CURRENT_VERSION,
+        notes: notes.iter().collect(),
+    };
+    let data = serde_json::to_vec_pretty(&document).map_err(|e| e.to_string())?;
+    write_atomic(&dir.join(NOTES_FILE), &crypto::seal(&data)?).map_err(|e| e.to_string())
+}
+
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::hard_link(from, to).or_else(|_| fs::copy(from, to).map(|_| ()))
+}
+
+/// Writes a backup of `notes` and the media they use into the backups of
+/// `workspace`, which need not be the active one any more. The backup is
+/// built under a hidden name and renamed into place, so a half-written one
+/// never shows up in `list`.
+pub fn create(workspace: &Path, kind: BackupKind, notes: &[Note]) -> Result<BackupInfo, String> {
+    let now = chrono::Utc::now();
+    let id = format!("{}-{}", kind.name(), now.format("%Y%m%dT%H%M%S%.3fZ"));
+    let root = backups_dir_in(workspace);
+    let media_in = workspace.join("media");
+    let tmp = root.join(format!(".{}.tmp", id));
+    let result = (|| -> Result<BackupInfo, String> {
+        let media_out = tmp.join("media");
+        fs::create_dir_all(media_out.join("meta")).map_err(|e| e.to_string())?;
+
+        let mut media = 0;
+        let mut missing_media = 0;
+        for name in referenced_media(notes) {
+            let path = match media::resolve_in(&media_in, &name) {
+                Ok(path) => path,
+                Err(_) => {
+                    missing_media += 1;
+                    continue;
+                }
+            };
+            link_or_copy(&path, &media_out.join(&name))
+                .map_err(|e| format!("Failed to back up {}: {}", name, e))?;
+            media += 1;
+            // Attachments keep their original name in a sidecar.
+            let stored = path.file_name().and_then(|n| n.to_str()).unwrap_or(&name);
+            let meta = media_in.join("meta").join(format!("{}.json", stored));
+            if meta.is_file() {
+                link_or_copy(
+                    &meta,
+                    &media_out.join("meta").join(format!("{}.json", name)),
+                )
+                .map_err(|e| e.to_string())?;
+            }
+        }
+
+        write_notes(&tmp, notes)?;
+
+        let info = BackupInfo {
+            id: id.clone(),
+            kind,
+            created_at: now.to_rfc3339(),
+            notes: notes.len(),
+            media,
+            missing_media,
+        };
+        let manifest = serde_json::to_vec_pretty(&info).map_err(|e| e.to_string())?;
+        write_atomic(&tmp.join(MANIFEST_FILE), &crypto::seal(&manifest)?)
+            .map_err(|e| e.to_string())?;
+        fs::rename(&tmp, root.join(&id)).map_err(|e| e.to_string())?;
+        Ok(info)
+    })();
+    if result.is_err() {
+        fs::remove_dir_all(&tmp).ok();
+    }
+    result.map_err(|e| format!("Failed to write backup: {}", e))
+}
+
+/// Backups of the active workspace, newest first.
+pub fn list() -> Vec<BackupInfo> {
+    list_in(&workspace_dir())
+}
+
+fn list_in(workspace: &Path) -> Vec<BackupInfo> {
+    let mut backups: Vec<BackupInfo> = fs::read_dir(backups_dir_in(workspace))
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+                .filter_map(|e| match read_manifest(&e.path()) {
+                    Ok(info) => Some(info),
+                    Err(err) => {
+                        eprintln!("skipping backup {}: {}", e.path().display(), err);
+                        None
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    backups.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    backups
+}
+
+/// Whether the newest backup of `kind` in `workspace` is old enough for
+/// another one.
+pub fn is_due(workspace: &Path, kind: BackupKind) -> bool {
+    let latest = list_in(workspace)
+        .into_iter()
+        .find(|b| b.kind == kind)
+        .and_then(|b| chrono::DateTime::parse_from_rfc3339(&b.created_at).ok());
+    match latest {
+        Some(at) => chrono::Utc::now().signed_duration_since(at) >= kind.interval(),
+        None => true,
+    }
+}
+
+/// Deletes the oldest backups of `kind` in `workspace` beyond the newest
+/// `keep`.
+pub fn prune(workspace: &Path, kind: BackupKind, keep: usize) {
+    for old in list_in(workspace)
+        .into_iter()
+        .filter(|b| b.kind == kind)
+        .skip(keep)
+    {
+        if let Err(e) = fs::remove_dir_all(backups_dir_in(workspace).join(&old.id)) {
+            eprintln!("failed to remove old backup {}: {}", old.id, e);
+        }
+    }
+}
+
+/// Takes a backup before a restore, keeping the last few of those.
+pub fn create_pre_restore(notes: &[Note]) -> Result<BackupInfo, String> {
+    let workspace = workspace_dir();
+    let info = create(&workspace, BackupKind::PreRestore, notes)?;
+    prune(&workspace, BackupKind::PreRestore, PRE_RESTORE_KEEP);
+    Ok(info)
+}
+
+/// Reads a backup's notes and puts back any of its media that is no longer
+/// in `media/`. Existing media files are left alone: a file with the same
+/// content name has the same content.
+pub fn restore(id: &str) -> Result<Vec<Note>, String> {
+    let dir = backup_dir(id)?;
+    let notes = read_notes(&dir)?;
+
+    let media_in = dir.join("media");
+    let media_out = media_dir();
+    if let Ok(entries) = fs::read_dir(&media_in) {
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name();
+            let path = entry.path();
+            if !path.is_file() || media::resolve(&name.to_string_lossy()).is_ok() {
+                continue;
+            }
+            link_or_copy(&path, &media_out.join(&name))
+                .map_err(|e| format!("Failed to restore {}: {}", name.to_string_lossy(), e))?;
+            let meta = media_in
+                .join("meta")
+                .join(format!("{}.json", name.to_string_lossy()));
+            if meta.is_file() {
+                fs::create_dir_all(meta_dir()).map_err(|e| e.to_string())?;
+                link_or_copy(
+                    &meta,
+                    &meta_dir().join(meta.file_name().unwrap_or_default()),
+                )
+                .map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    Ok(notes)
+}
+
+/// Replaces `note` in every backup of `workspace` that has it with `note`
+/// as given. A
+/// backup whose notes cannot be read is removed, since there is no telling
+/// whether it holds the note.
+pub fn scrub_note(workspace: &Path, note: &Note) -> Result<(), String> {
+    let Ok(entries) = fs::read_dir(backups_dir_in(workspace)) else {
+        return Ok(());
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let dir = entry.path();
+        // Backups still being written are checked by whoever writes them.
+        if entry.file_name().to_string_lossy().starts_with('.') || !dir.join(NOTES_FILE).is_file() {
+            continue;
+        }
+        let mut notes = match read_notes(&dir) {
+            Ok(notes) => notes,
+            Err(e) => {
+                eprintln!("removing unreadable backup {}: {}", dir.display(), e);
+                fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+                continue;
+            }
+        };
+        if let Some(old) = notes.iter_mut().find(|n| n.id == note.id) {
+            *old = note.clone();
+            write_notes(&dir, &notes)
+                .map_err(|e| format!("Failed to update backup {}: {}", dir.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_dir;
+    use std::time::Duration;
+
+    fn note(content: &str) -> Note {
+        Note {
+            content: content.to_string(),
+            ..Note::new()
+        }
+    }
+
+    // Backup ids are made from the time in milliseconds.
+    fn create_later(kind: BackupKind, notes: &[Note]) -> BackupInfo {
+        std::thread::sleep(Duration::from_millis(5));
+        create(&workspace_dir(), kind, notes).unwrap()
+    }
+
+    #[test]
+    fn restores_notes_and_missing_media() {
+        let _dir = test_dir();
+        let image = media_dir().join("backup-test.png");
+        fs::write(&image, b"png").unwrap();
+        let notes = vec![
+            note(r#"<img src="http://media.localhost/backup-test.png">"#),
+            note("<p>text</p>"),
+        ];
+        let info = create_later(BackupKind::Daily, &notes);
+        assert_eq!((info.notes, info.media, info.missing_media), (2, 1, 0));
+        assert_eq!(list()[0].id, info.id);
+
+        fs::remove_file(&image).unwrap();
+        let restored = restore(&info.id).unwrap();
+        let contents: Vec<_> = restored.iter().map(|n| n.content.as_str()).collect();
+        assert_eq!(contents, [notes[0].content.as_str(), "<p>text</p>"]);
+        assert_eq!(fs::read(&image).unwrap(), b"png");
+        assert!(restore("../backups").is_err());
+    }
+
+    #[test]
+    fn is_due_once_the_interval_has_passed() {
+        let _dir = test_dir();
+        assert!(is_due(&workspace_dir(), BackupKind::Daily));
+        create_later(BackupKind::Daily, &[]);
+        assert!(!is_due(&workspace_dir(), BackupKind::Daily));
+        assert!(is_due(&workspace_dir(), BackupKind::Weekly));
+        create_later(BackupKind::PreRestore, &[]);
+        assert!(is_due(&workspace_dir(), BackupKind::PreRestore));
+    }
+
+    #[test]
+    fn prunes_the_oldest_of_a_kind() {
+        let _dir = test_dir();
+        let weekly = create_later(BackupKind::Weekly, &[]);
+        let daily: Vec<_> = (0..3)
+            .map(|_| create_later(BackupKind::Daily, &[]).id)
+            .collect();
+        prune(&workspace_dir(), BackupKind::Daily, 2);
+        let left: Vec<_> = list().into_iter().map(|b| b.id).collect();
+        assert_eq!(left, [daily[2].as_str(), &daily[1], &weekly.id]);
+    }
+
+    #[test]
+    fn scrubbing_replaces_the_note_in_every_backup() {
+        let _dir = test_dir();
+        let plain = note("<p>secret</p>");
+        let other = note("<p>other</p>");
+        let first = create_later(BackupKind::Daily, &[plain.clone(), other.clone()]);
+        let second = create_later(BackupKind::Weekly, std::slice::from_ref(&plain));
+        let locked = Note {
+            content: "locked".to_string(),
+            ..plain.clone()
+        };
+        scrub_note(&workspace_dir(), &locked).unwrap();
+        for id in [&first.id, &second.id] {
+            let notes = restore(id).unwrap();
+            assert!(notes.iter().all(|n| !n.content.contains("secret")));
+            assert_eq!(notes[0].content, "locked");
+        }
+        assert_eq!(restore(&first.id).unwrap()[1].content, other.content);
+    }
+}
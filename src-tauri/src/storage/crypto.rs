@@ -0,0 +1,227 @@
+//! Optional encryption at rest.
+//!
+//! When a passphrase is set, a 256-bit key is derived from it with Argon2id
+//! and every file the store writes is sealed with XChaCha20-Poly1305. The
+//! key only lives in memory; settings.json keeps the KDF parameters and a
+//! sealed verifier so a wrong passphrase is rejected before any note is read.
+//!
+//! Sealed files start with `MAGIC`, so plaintext files left over from before
+//! encryption was turned on (or from a half-finished conversion) still load.
+//! Once the conversion has finished, `encryption_complete` is set in settings
+//! and plaintext is refused, so a file swapped for an unencrypted one cannot
+//! slip notes or images into the store.
+
+use crate::settings;
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::sync::RwLock;
+use zeroize::Zeroize;
+
+const MAGIC: &[u8] = b"SNENC1\0";
+const NONCE_LEN: usize = 24;
+/// Marks a sealed value inside a text format (journal lines, SQLite rows).
+const TEXT_PREFIX: &str = "enc1:";
+const VERIFIER: &[u8] = b"stickynotes";
+
+// OWASP's recommended Argon2id baseline: 19 MiB, two passes.
+const DEFAULT_M_COST: u32 = 19 * 1024;
+const DEFAULT_T_COST: u32 = 2;
+const DEFAULT_P_COST: u32 = 1;
+
+/// A 256-bit key. Every copy is wiped from memory when it is dropped.
+#[derive(Clone)]
+pub struct Key(chacha20poly1305::Key);
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        self.0.as_mut_slice().zeroize();
+    }
+}
+
+static KEY: RwLock<Option<Key>> = RwLock::new(None);
+
+/// What is needed to re-derive and check the key, stored in settings.json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub salt: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+    /// A known value sealed with the key, used to check the passphrase.
+    pub verifier: String,
+}
+
+fn b64() -> base64::engine::GeneralPurpose {
+    base64::engine::general_purpose::STANDARD
+}
+
+fn derive(
+    passphrase: &str,
+    salt: &[u8],
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+) -> Result<Key, String> {
+    let params = Params::new(m_cost, t_cost, p_cost, Some(32)).map_err(|e| e.to_string())?;
+    let mut key = Key(chacha20poly1305::Key::default());
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key.0)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+/// Derives a key for a new passphrase with a fresh random salt.
+pub fn new_key(passphrase: &str) -> Result<(Key, KdfParams), String> {
+    if passphrase.is_empty() {
+        return Err("Passphrase must not be empty".to_string());
+    }
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive(
+        passphrase,
+        &salt,
+        DEFAULT_M_COST,
+        DEFAULT_T_COST,
+        DEFAULT_P_COST,
+    )?;
+    let verifier = seal_with(Some(&key), VERIFIER)?;
+    let params = KdfParams {
+        salt: b64().encode(salt),
+        m_cost: DEFAULT_M_COST,
+        t_cost: DEFAULT_T_COST,
+        p_cost: DEFAULT_P_COST,
+        verifier: b64().encode(verifier),
+    };
+    Ok((key, params))
+}
+
+/// Re-derives the key for `passphrase` and checks it against the verifier.
+pub fn unlock_key(passphrase: &str, params: &KdfParams) -> Result<Key, String> {
+    let salt = b64().decode(&params.salt).map_err(|e| e.to_string())?;
+    let key = derive(
+        passphrase,
+        &salt,
+        params.m_cost,
+        params.t_cost,
+        params.p_cost,
+    )?;
+    let verifier = b64().decode(&params.verifier).map_err(|e| e.to_string())?;
+    match open_with(Some(&key), &verifier) {
+        Ok(plain) if plain == VERIFIER => Ok(key),
+        _ => Err("Wrong passphrase".to_string()),
+    }
+}
+
+pub fn set_key(key: Option<Key>) {
+    if let Ok(mut current) = KEY.write() {
+        *current = key;
+    }
+}
+
+pub fn current_key() -> Option<Key> {
+    KEY.read().ok().and_then(|k| k.clone())
+}
+
+/// True while encryption is on but the passphrase has not been entered yet.
+pub fn is_locked() -> bool {
+    settings::current().encryption.is_some() && current_key().is_none()
+}
+
+/// Hex HMAC-SHA256 of `data` under `key`. Stands in for a plain hash where
+/// the hash would show whether a known file is in the store.
+pub fn keyed_digest(key: &Key, data: &[u8]) -> String {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key.0).expect("HMAC takes any key size");
+    mac.update(b"stickynotes media name\0");
+    mac.update(data);
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts `data` under `key`, or returns it unchanged when there is none.
+pub fn seal_with(key: Option<&Key>, data: &[u8]) -> Result<Vec<u8>, String> {
+    let key = match key {
+        Some(key) => key,
+        None => return Ok(data.to_vec()),
+    };
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = XChaCha20Poly1305::new(&key.0)
+        .encrypt(&nonce, data)
+        .map_err(|_| "Failed to encrypt data".to_string())?;
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+/// Whether plaintext should be refused where a sealed value is expected.
+fn plaintext_allowed(key: Option<&Key>) -> bool {
+    key.is_none() || !settings::current().encryption_complete
+}
+
+/// Decrypts sealed `data`. Plaintext is passed through as-is, unless the
+/// store has been fully encrypted.
+pub fn open_with(key: Option<&Key>, data: &[u8]) -> Result<Vec<u8>, String> {
+    let body = match data.strip_prefix(MAGIC) {
+        Some(body) => body,
+        None if plaintext_allowed(key) => return Ok(data.to_vec()),
+        None => return Err("Found unencrypted data in an encrypted store".to_string()),
+    };
+    let key = key.ok_or_else(|| "Notes are locked".to_string())?;
+    if body.len() < NONCE_LEN {
+        return Err("Encrypted data is truncated".to_string());
+    }
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    XChaCha20Poly1305::new(&key.0)
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Encrypted data is damaged or was sealed with another key".to_string())
+}
+
+pub fn seal(data: &[u8]) -> Result<Vec<u8>, String> {
+    seal_with(current_key().as_ref(), data)
+}
+
+pub fn open(data: &[u8]) -> Result<Vec<u8>, String> {
+    open_with(current_key().as_ref(), data)
+}
+
+/// Like `seal_with`, for values that have to stay valid text.
+pub fn seal_text_with(key: Option<&Key>, text: &str) -> Result<String, String> {
+    if key.is_none() {
+        return Ok(text.to_string());
+    }
+    let sealed = seal_with(key, text.as_bytes())?;
+    Ok(format!("{}{}", TEXT_PREFIX, b64().encode(sealed)))
+}
+
+pub fn open_text_with(key: Option<&Key>, text: &str) -> Result<String, String> {
+    let encoded = match text.strip_prefix(TEXT_PREFIX) {
+        Some(encoded) => encoded,
+        None if plaintext_allowed(key) => return Ok(text.to_string()),
+        None => return Err("Found unencrypted data in an encrypted store".to_string()),
+    };
+    let sealed = b64().decode(encoded.trim()).map_err(|e| e.to_string())?;
+    let plain = open_with(key, &sealed)?;
+    String::from_utf8(plain).map_err(|e| e.to_string())
+}
+
+pub fn seal_text(text: &str) -> Result<String, String> {
+    seal_text_with(current_key().as_ref(), text)
+}
+
+pub fn open_text(text: &str) -> Result<String, String> {
+    open_text_with(current_key().as_ref(), text)
+}
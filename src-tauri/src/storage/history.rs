@@ -0,0 +1,221 @@
+//! Earlier versions of each note's content, so a note that was overwritten
+//! by mistake can be brought back.
+//!
+//! `record_change` is called on every save but only keeps a version every
+//! `history_interval_minutes`, or right away when an edit replaces most of
+//! the note. Each note's versions live in one sealed file under `history/`,
+//! oldest first, capped by `history_max_versions` and `history_max_bytes`.
+//! Locked notes are not versioned, since the history would hold their
+//! content in the clear.
+
+use super::workspace::workspace_dir;
+use super::{crypto, media, write_atomic};
+use crate::settings;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Edits that lose fewer bytes than this are never significant on their own.
+const SIGNIFICANT_BYTES: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoteVersion {
+    pub id: String,
+    pub created_at: String,
+    pub content: String,
+}
+
+/// A version without its content, for listing.
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    pub id: String,
+    pub created_at: String,
+    pub size: usize,
+}
+
+fn history_dir() -> PathBuf {
+    workspace_dir().join("history")
+}
+
+fn path_for(note_id: &str) -> Result<PathBuf, String> {
+    if note_id.is_empty() || note_id.contains(['/', '\\']) || note_id.starts_with('.') {
+        return Err(format!("Invalid note id {:?}", note_id));
+    }
+    Ok(history_dir().join(format!("{}.json", note_id)))
+}
+
+fn load(note_id: &str) -> Result<Vec<NoteVersion>, String> {
+    let data = match fs::read(path_for(note_id)?) {
+        Ok(data) => data,
+        Err(_) => return Ok(Vec::new()),
+    };
+    serde_json::from_slice(&crypto::open(&data)?).map_err(|e| e.to_string())
+}
+
+fn save(note_id: &str, versions: &[NoteVersion]) -> Result<(), String> {
+    fs::create_dir_all(history_dir()).map_err(|e| e.to_string())?;
+    let data = serde_json::to_vec(versions).map_err(|e| e.to_string())?;
+    write_atomic(&path_for(note_id)?, &crypto::seal(&data)?)
+        .map_err(|e| format!("Failed to save note history: {}", e))
+}
+
+/// Whether going from `old` to `new` throws away most of `old`, as when
+/// everything is selected and typed over.
+fn significant_change(old: &str, new: &str) -> bool {
+    let (old, new) = (old.as_bytes(), new.as_bytes());
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let lost = old.len() - prefix - suffix;
+    lost >= SIGNIFICANT_BYTES && lost * 2 > old.len()
+}
+
+/// Drops the oldest versions until both caps are met. The newest version is
+/// always kept.
+fn prune(versions: &mut Vec<NoteVersion>, max_versions: usize, max_bytes: u64) {
+    let mut total: u64 = versions.iter().map(|v| v.content.len() as u64).sum();
+    let mut drop = 0;
+    while versions.len() - drop > 1
+        && (versions.len() - drop > max_versions.max(1) || total > max_bytes)
+    {
+        total -= versions[drop].content.len() as u64;
+        drop += 1;
+    }
+    versions.drain(..drop);
+}
+
+/// Keeps `content` as a new version of the note, unless it is empty or the
+/// same as the latest one. Returns whether a version was added.
+pub fn record(note_id: &str, content: &str) -> Result<bool, String> {
+    let mut versions = load(note_id)?;
+    if content.is_empty() || versions.last().is_some_and(|v| v.content == content) {
+        return Ok(false);
+    }
+    versions.push(NoteVersion {
+        id: uuid::Uuid::new_v4().to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        content: content.to_string(),
+    });
+    let settings = settings::current();
+    prune(
+        &mut versions,
+        settings.history_max_versions,
+        settings.history_max_bytes,
+    );
+    save(note_id, &versions)?;
+    Ok(true)
+}
+
+/// Called before a note's content goes from `old` to `new`. Keeps `old` if
+/// the last version is older than `history_interval_minutes` or the edit
+/// replaces most of it.
+pub fn record_change(note_id: &str, old: &str, new: &str) -> Result<bool, String> {
+    if old == new {
+        return Ok(false);
+    }
+    let interval = Duration::from_secs(settings::current().history_interval_minutes * 60);
+    let due = fs::metadata(path_for(note_id)?)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .is_none_or(|age| age >= interval);
+    if !due && !significant_change(old, new) {
+        return Ok(false);
+    }
+    record(note_id, old)
+}
+
+/// The note's versions, newest first.
+pub fn list(note_id: &str) -> Result<Vec<VersionInfo>, String> {
+    Ok(load(note_id)?
+        .into_iter()
+        .rev()
+        .map(|v| VersionInfo {
+            size: v.content.len(),
+            id: v.id,
+            created_at: v.created_at,
+        })
+        .collect())
+}
+
+pub fn get(note_id: &str, version_id: &str) -> Result<NoteVersion, String> {
+    load(note_id)?
+        .into_iter()
+        .find(|v| v.id == version_id)
+        .ok_or_else(|| format!("Version {} not found", version_id))
+}
+
+/// Media files any kept version refers to, so GC leaves them in place for
+/// as long as the version could be restored.
+pub fn media_references() -> HashSet<String> {
+    let mut referenced = HashSet::new();
+    let entries = match fs::read_dir(history_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return referenced,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(note_id) = name.strip_suffix(".json") else {
+            continue;
+        };
+        match load(note_id) {
+            Ok(versions) => {
+                for version in &versions {
+                    referenced.extend(media::media_references(&version.content).map(String::from));
+                }
+            }
+            Err(e) => eprintln!("failed to read history of note {}: {}", note_id, e),
+        }
+    }
+    referenced
+}
+
+/// Deletes the note's history, e.g. once the note itself is gone.
+pub fn remove(note_id: &str) {
+    if let Ok(path) = path_for(note_id) {
+        fs::remove_file(path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(content: &str) -> NoteVersion {
+        NoteVersion {
+            id: String::new(),
+            created_at: String::new(),
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn overwriting_most_of_a_note_is_significant() {
+        let old = "<p>".to_string() + &"shopping list ".repeat(20) + "</p>";
+        assert!(significant_change(&old, "<p>oops</p>"));
+        assert!(!significant_change(&old, &old.replacen("list", "lists", 1)));
+        assert!(!significant_change("<p>short</p>", "<p>x</p>"));
+        assert!(!significant_change("", "<p>new text</p>"));
+    }
+
+    #[test]
+    fn prunes_oldest_versions_first() {
+        let mut versions: Vec<_> = ["a", "bb", "ccc", "dddd"].map(version).into();
+        prune(&mut versions, 3, u64::MAX);
+        assert_eq!(versions[0].content, "bb");
+
+        prune(&mut versions, 10, 7);
+        let kept: Vec<_> = versions.iter().map(|v| v.content.as_str()).collect();
+        assert_eq!(kept, ["ccc", "dddd"]);
+
+        prune(&mut versions, 10, 1);
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].content, "dddd");
+    }
+}
@@ -0,0 +1,451 @@
+//! Checks pasted images before they are stored.
+//!
+//! The format is taken from the bytes themselves; the mime type the webview
+//! reports only has to agree with it. SVG is the one format that can carry
+//! script, so it is rewritten without scripts, event handlers, foreign
+//! content or links that leave the document.
+//!
+//! Large raster images also get downscaled variants for display, so a note
+//! window does not decode a full-resolution screenshot on every render.
+
+use ::image::codecs::jpeg::JpegEncoder;
+use ::image::imageops::FilterType;
+use ::image::ImageReader;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Cursor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Svg,
+}
+
+impl ImageFormat {
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime {
+            "image/png" => Some(Self::Png),
+            "image/jpeg" | "image/jpg" => Some(Self::Jpeg),
+            "image/gif" => Some(Self::Gif),
+            "image/webp" => Some(Self::Webp),
+            "image/svg+xml" => Some(Self::Svg),
+            _ => None,
+        }
+    }
+
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        match ext {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "gif" => Some(Self::Gif),
+            "webp" => Some(Self::Webp),
+            "svg" => Some(Self::Svg),
+            _ => None,
+        }
+    }
+
+    /// Identifies the format from its magic number.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(Self::Webp)
+        } else if looks_like_svg(bytes) {
+            Some(Self::Svg)
+        } else {
+            None
+        }
+    }
+
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Gif => "gif",
+            Self::Webp => "webp",
+            Self::Svg => "svg",
+        }
+    }
+
+    /// What a downscaled copy is encoded as. GIFs would lose their
+    /// animation and SVGs scale by themselves, so neither gets variants.
+    pub fn variant_format(self) -> Option<Self> {
+        match self {
+            Self::Png | Self::Webp => Some(Self::Png),
+            Self::Jpeg => Some(Self::Jpeg),
+            Self::Gif | Self::Svg => None,
+        }
+    }
+
+    fn codec(self) -> ::image::ImageFormat {
+        match self {
+            Self::Png => ::image::ImageFormat::Png,
+            Self::Jpeg => ::image::ImageFormat::Jpeg,
+            Self::Gif => ::image::ImageFormat::Gif,
+            Self::Webp => ::image::ImageFormat::WebP,
+            // Never decoded; `variant_format` rules it out.
+            Self::Svg => ::image::ImageFormat::Png,
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
+            Self::Webp => "image/webp",
+            Self::Svg => "image/svg+xml",
+        }
+    }
+}
+
+/// A downscaled copy of a stored image, picked with `?size=` on a media URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// Capped for showing inside a note window.
+    Display,
+    /// Small enough for lists and previews.
+    Thumb,
+}
+
+impl Variant {
+    pub const ALL: [Variant; 2] = [Variant::Display, Variant::Thumb];
+
+    /// Reads `size=display` or `size=thumb` from a URL query. Anything
+    /// else, including `size=original`, means the original file.
+    pub fn from_query(query: &str) -> Option<Self> {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == "size")
+            .and_then(|(_, value)| match value {
+                "display" => Some(Self::Display),
+                "thumb" => Some(Self::Thumb),
+                _ => None,
+            })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Display => "display",
+            Self::Thumb => "thumb",
+        }
+    }
+
+    /// Longest side in pixels. Display leaves room for a note window
+    /// stretched across a retina screen.
+    fn max_side(self) -> u32 {
+        match self {
+            Self::Display => 1600,
+            Self::Thumb => 256,
+        }
+    }
+}
+
+/// Scales `bytes` down to fit `variant`. Returns `None` when the format has
+/// no variants or the image already fits, in which case the original is
+/// served as-is.
+pub fn downscale(
+    bytes: &[u8],
+    format: ImageFormat,
+    variant: Variant,
+) -> Result<Option<Vec<u8>>, String> {
+    let output = match format.variant_format() {
+        Some(output) => output,
+        None => return Ok(None),
+    };
+    let max = variant.max_side();
+    // Only the header is read here, so small images stay cheap.
+    let (width, height) = ImageReader::with_format(Cursor::new(bytes), format.codec())
+        .into_dimensions()
+        .map_err(|e| format!("Failed to read image size: {}", e))?;
+    if width <= max && height <= max {
+        return Ok(None);
+    }
+
+    let image = ::image::load_from_memory_with_format(bytes, format.codec())
+        .map_err(|e| format!("Failed to decode image: {}", e))?
+        .resize(max, max, FilterType::Lanczos3);
+    let mut out = Vec::new();
+    let encoded = match output {
+        ImageFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut out, 85)),
+        _ => image.write_to(&mut Cursor::new(&mut out), output.codec()),
+    };
+    encoded.map_err(|e| format!("Failed to encode image: {}", e))?;
+    Ok(Some(out))
+}
+
+/// Why `save_image` turned an upload down. Serialized with a `kind` tag so
+/// the frontend can tell the cases apart.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ImageError {
+    InvalidBase64 {
+        message: String,
+    },
+    TooLarge {
+        size: u64,
+        max: u64,
+    },
+    UnsupportedType {
+        mime_type: String,
+    },
+    UnrecognizedContent,
+    TypeMismatch {
+        declared: ImageFormat,
+        detected: ImageFormat,
+    },
+    InvalidSvg {
+        message: String,
+    },
+    Storage {
+        message: String,
+    },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBase64 { message } => write!(f, "Invalid base64: {}", message),
+            Self::TooLarge { size, max } => {
+                write!(f, "Image is {} bytes, the limit is {}", size, max)
+            }
+            Self::UnsupportedType { mime_type } => {
+                write!(f, "Unsupported image type: {}", mime_type)
+            }
+            Self::UnrecognizedContent => write!(f, "Data is not a supported image"),
+            Self::TypeMismatch { declared, detected } => write!(
+                f,
+                "Image was sent as {} but is {}",
+                declared.mime(),
+                detected.mime()
+            ),
+            Self::InvalidSvg { message } => write!(f, "Invalid SVG: {}", message),
+            Self::Storage { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<String> for ImageError {
+    fn from(message: String) -> Self {
+        Self::Storage { message }
+    }
+}
+
+fn looks_like_svg(bytes: &[u8]) -> bool {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text.trim_start_matches('\u{feff}').trim_start(),
+        Err(_) => return false,
+    };
+    text.starts_with('<') && text.contains("<svg")
+}
+
+/// Checks `bytes` against `mime_type` and `max` and returns the format,
+/// along with the bytes to store (sanitized, for SVG).
+pub fn validate<'a>(
+    bytes: &'a [u8],
+    mime_type: &str,
+    max: u64,
+) -> Result<(ImageFormat, Cow<'a, [u8]>), ImageError> {
+    let size = bytes.len() as u64;
+    if size > max {
+        return Err(ImageError::TooLarge { size, max });
+    }
+    let declared =
+        ImageFormat::from_mime(mime_type).ok_or_else(|| ImageError::UnsupportedType {
+            mime_type: mime_type.to_string(),
+        })?;
+    let detected = ImageFormat::detect(bytes).ok_or(ImageError::UnrecognizedContent)?;
+    if declared != detected {
+        return Err(ImageError::TypeMismatch { declared, detected });
+    }
+    let bytes = match detected {
+        ImageFormat::Svg => Cow::Owned(sanitize_svg(bytes)?),
+        _ => Cow::Borrowed(bytes),
+    };
+    Ok((detected, bytes))
+}
+
+// Elements dropped together with everything inside them.
+const BLOCKED_ELEMENTS: &[&str] = &[
+    "script",
+    "foreignobject",
+    "iframe",
+    "embed",
+    "object",
+    "audio",
+    "video",
+    "handler",
+    "listener",
+    // Style sheets can pull in fonts and images with url() and @import.
+    "style",
+    // Animations can rewrite an href to a javascript: URL after the fact.
+    "set",
+    "animate",
+    "animatemotion",
+    "animatetransform",
+];
+
+fn is_blocked(name: &[u8]) -> bool {
+    let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+    BLOCKED_ELEMENTS.contains(&name.as_str())
+}
+
+/// Links may only point inside the document or at embedded raster images.
+fn is_safe_link(value: &str) -> bool {
+    let value: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    value.starts_with('#')
+        || ["png", "jpeg", "gif", "webp"]
+            .iter()
+            .any(|f| value.starts_with(&format!("data:image/{};", f)))
+}
+
+/// Whether a style or presentation value loads anything from outside the
+/// document: an `@import`, or a `url()` that is not a `#fragment`.
+fn loads_external(value: &str) -> bool {
+    if value.contains("@import") {
+        return true;
+    }
+    value.split("url(").skip(1).any(|rest| {
+        let target = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '"' || c == '\'');
+        !target.starts_with('#')
+    })
+}
+
+/// Copies `element` without event handlers, unsafe links or scriptable
+/// styles. Returns `None` if an attribute cannot be parsed.
+fn clean_element(element: &BytesStart) -> Option<BytesStart<'static>> {
+    let name = String::from_utf8_lossy(element.name().as_ref()).into_owned();
+    let mut clean = BytesStart::new(name);
+    for attr in element.attributes() {
+        let attr = attr.ok()?;
+        let local = String::from_utf8_lossy(attr.key.local_name().as_ref()).to_ascii_lowercase();
+        let value = attr.unescape_value().ok()?.to_ascii_lowercase();
+        let keep = if local.starts_with("on") {
+            false
+        } else if local == "href" {
+            is_safe_link(&value)
+        } else {
+            !value.contains("javascript:")
+                && !value.contains("expression(")
+                && !loads_external(&value)
+        };
+        if keep {
+            clean.push_attribute((attr.key.as_ref(), attr.value.as_ref()));
+        }
+    }
+    Some(clean)
+}
+
+/// Rewrites an SVG document without anything that could run script or
+/// load content from elsewhere.
+pub fn sanitize_svg(bytes: &[u8]) -> Result<Vec<u8>, ImageError> {
+    let invalid = |message: String| ImageError::InvalidSvg { message };
+    let text = std::str::from_utf8(bytes).map_err(|e| invalid(e.to_string()))?;
+    let mut reader = Reader::from_str(text.trim_start_matches('\u{feff}'));
+    let mut writer = Writer::new(Vec::new());
+    // Depth inside a blocked element; nothing is written while it is > 0.
+    let mut skipping = 0usize;
+    let mut saw_svg = false;
+
+    loop {
+        let event = reader.read_event().map_err(|e| invalid(e.to_string()))?;
+        let out = match event {
+            Event::Eof => break,
+            Event::Start(e) => {
+                if skipping > 0 || is_blocked(e.local_name().as_ref()) {
+                    skipping += 1;
+                    continue;
+                }
+                saw_svg |= e.local_name().as_ref() == b"svg";
+                let clean = clean_element(&e).ok_or_else(|| invalid("bad attribute".into()))?;
+                Event::Start(clean)
+            }
+            Event::End(e) => {
+                if skipping > 0 {
+                    skipping -= 1;
+                    continue;
+                }
+                Event::End(e)
+            }
+            Event::Empty(e) => {
+                if skipping > 0 || is_blocked(e.local_name().as_ref()) {
+                    continue;
+                }
+                saw_svg |= e.local_name().as_ref() == b"svg";
+                let clean = clean_element(&e).ok_or_else(|| invalid("bad attribute".into()))?;
+                Event::Empty(clean)
+            }
+            // Entity declarations and processing instructions are never
+            // needed to draw an image.
+            Event::DocType(_) | Event::PI(_) | Event::Comment(_) => continue,
+            _ if skipping > 0 => continue,
+            other => other,
+        };
+        writer
+            .write_event(out)
+            .map_err(|e| invalid(e.to_string()))?;
+    }
+
+    if !saw_svg {
+        return Err(invalid("no <svg> element".into()));
+    }
+    Ok(writer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(svg: &str) -> String {
+        String::from_utf8(sanitize_svg(svg.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn drops_script_and_event_handlers() {
+        let out = clean(
+            r#"<svg onload="alert(1)"><script>alert(2)</script><rect onclick="alert(3)" width="1"/></svg>"#,
+        );
+        assert!(!out.contains("alert"), "{}", out);
+        assert!(out.contains(r#"width="1""#), "{}", out);
+    }
+
+    #[test]
+    fn drops_javascript_links_and_animations() {
+        let out = clean(
+            r##"<svg><a href="javascript:alert(1)"><set attributeName="href" to="javascript:alert(2)"/></a><a href="#x"/></svg>"##,
+        );
+        assert!(!out.contains("javascript"), "{}", out);
+        assert!(!out.contains("<set"), "{}", out);
+        assert!(out.contains(r##"href="#x""##), "{}", out);
+    }
+
+    #[test]
+    fn drops_style_sheets_and_external_urls() {
+        let out = clean(concat!(
+            r#"<svg><style>@import "https://example.com/a.css";</style>"#,
+            r#"<rect fill="url('https://example.com/p.png')" style="background: url(https://example.com/b.png)"/>"#,
+            r#"<rect fill="url(#grad)"/></svg>"#,
+        ));
+        assert!(!out.contains("example.com"), "{}", out);
+        assert!(!out.contains("<style"), "{}", out);
+        assert!(out.contains(r#"fill="url(#grad)""#), "{}", out);
+    }
+}
@@ -0,0 +1,143 @@
+//! Adds a file to media from a path on disk or from raw bytes, sending it
+//! down the image or the attachment path depending on its type. Unlike
+//! `save_image` and `save_attachment`, nothing is base64-encoded on the way.
+
+use super::attachment::{self, Attachment, AttachmentError};
+use super::image::{self, ImageError, ImageFormat};
+use super::media;
+use crate::settings;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Imported {
+    Image { file: String },
+    Attachment(Attachment),
+}
+
+/// Whichever of the two typed errors applies, or a request that was turned
+/// down before the file was looked at. All of them carry a `kind` tag.
+#[derive(Debug, Clone)]
+pub enum ImportError {
+    Image(ImageError),
+    Attachment(AttachmentError),
+    /// `import_media` was given a path that was not just dropped on a
+    /// window.
+    NotDropped {
+        path: String,
+    },
+    /// `import_media_bytes` was not sent a raw body.
+    InvalidBody,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Image(e) => e.fmt(f),
+            Self::Attachment(e) => e.fmt(f),
+            Self::NotDropped { path } => write!(f, "{} was not dropped on a note", path),
+            Self::InvalidBody => write!(f, "Expected a raw request body"),
+        }
+    }
+}
+
+impl Serialize for ImportError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        match self {
+            Self::Image(e) => e.serialize(serializer),
+            Self::Attachment(e) => e.serialize(serializer),
+            Self::NotDropped { path } => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("kind", "not_dropped")?;
+                map.serialize_entry("path", path)?;
+                map.end()
+            }
+            Self::InvalidBody => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("kind", "invalid_body")?;
+                map.end()
+            }
+        }
+    }
+}
+
+impl From<ImageError> for ImportError {
+    fn from(e: ImageError) -> Self {
+        Self::Image(e)
+    }
+}
+
+impl From<AttachmentError> for ImportError {
+    fn from(e: AttachmentError) -> Self {
+        Self::Attachment(e)
+    }
+}
+
+fn ext_of(name: &str) -> String {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// Validates an image against `mime_type` and stores it.
+pub fn store_image(bytes: &[u8], mime_type: &str, max: u64) -> Result<String, ImageError> {
+    let (format, bytes) = image::validate(bytes, mime_type, max)?;
+    Ok(media::store(&bytes, format.ext())?)
+}
+
+/// Imports the file at `path`. Images are read whole, since they are
+/// checked and downscaled in memory; attachments are streamed.
+pub fn import_path(path: &Path) -> Result<Imported, ImportError> {
+    let settings = settings::current();
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or_default();
+    let format = match ImageFormat::from_ext(&ext_of(name)) {
+        Some(format) => format,
+        None => {
+            let attachment = attachment::import(path, settings.max_attachment_bytes)?;
+            return Ok(Imported::Attachment(attachment));
+        }
+    };
+
+    let max = settings.max_image_bytes;
+    let storage_error = |e: std::io::Error| ImageError::Storage {
+        message: format!("Failed to read {}: {}", name, e),
+    };
+    let size = fs::metadata(path).map_err(storage_error)?.len();
+    if size > max {
+        return Err(ImageError::TooLarge { size, max }.into());
+    }
+    let bytes = fs::read(path).map_err(storage_error)?;
+    let file = store_image(&bytes, format.mime(), max)?;
+    Ok(Imported::Image { file })
+}
+
+/// Imports bytes sent over raw IPC. The type comes from `mime_type` when it
+/// names an image, otherwise from the extension of `file_name`.
+pub fn import_bytes(
+    bytes: &[u8],
+    file_name: &str,
+    mime_type: &str,
+) -> Result<Imported, ImportError> {
+    let settings = settings::current();
+    let format =
+        ImageFormat::from_mime(mime_type).or_else(|| ImageFormat::from_ext(&ext_of(file_name)));
+    match format {
+        Some(format) => {
+            let file = store_image(bytes, format.mime(), settings.max_image_bytes)?;
+            Ok(Imported::Image { file })
+        }
+        None => {
+            let attachment = attachment::save(bytes, file_name, settings.max_attachment_bytes)?;
+            Ok(Imported::Attachment(attachment))
+        }
+    }
+}
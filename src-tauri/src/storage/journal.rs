@@ -0,0 +1,309 @@
+//! Append-only change log that sits next to the notes.json snapshot.
+//!
+//! The JSON backend appends one line per change instead of rewriting the
+//! snapshot, and folds the journal back into notes.json once it grows past
+//! `COMPACT_AFTER_RECORDS` or `COMPACT_AFTER`. Records carry whole notes or
+//! absolute positions, so replaying a journal over a snapshot that already
+//! contains some of its changes gives the same result.
+
+use super::crypto;
+use super::repository::Change;
+use super::workspace_dir;
+use crate::notes::Note;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+const COMPACT_AFTER_RECORDS: usize = 500;
+const COMPACT_AFTER: Duration = Duration::from_secs(15 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum JournalRecord {
+    Create {
+        at: String,
+        note: Note,
+    },
+    Update {
+        at: String,
+        note: Note,
+    },
+    Move {
+        at: String,
+        id: String,
+        x: f64,
+        y: f64,
+        width: f64,
+        height: f64,
+    },
+    Delete {
+        at: String,
+        id: String,
+    },
+}
+
+impl JournalRecord {
+    fn id(&self) -> &str {
+        match self {
+            JournalRecord::Create { note, .. } | JournalRecord::Update { note, .. } => &note.id,
+            JournalRecord::Move { id, .. } | JournalRecord::Delete { id, .. } => id,
+        }
+    }
+
+    fn at(&self) -> &str {
+        match self {
+            JournalRecord::Create { at, .. }
+            | JournalRecord::Update { at, .. }
+            | JournalRecord::Move { at, .. }
+            | JournalRecord::Delete { at, .. } => at,
+        }
+    }
+}
+
+fn journal_path() -> PathBuf {
+    workspace_dir().join("notes.journal")
+}
+
+fn only_geometry_changed(old: &Note, new: &Note) -> bool {
+    let mut moved = old.clone();
+    moved.x = new.x;
+    moved.y = new.y;
+    moved.width = new.width;
+    moved.height = new.height;
+    serde_json::to_value(&moved).ok() == serde_json::to_value(new).ok()
+}
+
+/// Describes `change` relative to the notes it is about to be applied to.
+pub fn record_for(notes: &HashMap<String, Note>, change: &Change) -> Option<JournalRecord> {
+    let at = chrono::Utc::now().to_rfc3339();
+    match change {
+        Change::Put(note) => match notes.get(&note.id) {
+            None => Some(JournalRecord::Create {
+                at,
+                note: Note::clone(note),
+            }),
+            Some(old) if only_geometry_changed(old, note) => Some(JournalRecord::Move {
+                at,
+                id: note.id.clone(),
+                x: note.x,
+                y: note.y,
+                width: note.width,
+                height: note.height,
+            }),
+            Some(_) => Some(JournalRecord::Update {
+                at,
+                note: Note::clone(note),
+            }),
+        },
+        Change::Delete(id) if notes.contains_key(id) => {
+            Some(JournalRecord::Delete { at, id: id.clone() })
+        }
+        Change::Delete(_) => None,
+    }
+}
+
+pub fn apply_record(notes: &mut HashMap<String, Note>, record: JournalRecord) {
+    match record {
+        JournalRecord::Create { note, .. } | JournalRecord::Update { note, .. } => {
+            notes.insert(note.id.clone(), note);
+        }
+        JournalRecord::Move {
+            id,
+            x,
+            y,
+            width,
+            height,
+            ..
+        } => {
+            if let Some(note) = notes.get_mut(&id) {
+                note.x = x;
+                note.y = y;
+                note.width = width;
+                note.height = height;
+            }
+        }
+        JournalRecord::Delete { id, .. } => {
+            notes.remove(&id);
+        }
+    }
+}
+
+/// Appends records and fsyncs, so each batch is durable once this returns.
+pub fn append(records: &[JournalRecord]) -> io::Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let mut data = String::new();
+    for record in records {
+        let line = serde_json::to_string(record)?;
+        data.push_str(&crypto::seal_text(&line).map_err(io::Error::other)?);
+        data.push('\n');
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path())?;
+    file.write_all(data.as_bytes())?;
+    file.sync_data()
+}
+
+/// Reads every record in the journal. A torn last line from a crash
+/// mid-append is skipped.
+pub fn read() -> Vec<JournalRecord> {
+    let file = match File::open(journal_path()) {
+        Ok(file) => file,
+        Err(_) => return Vec::new(),
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            match crypto::open_text(&line)
+                .and_then(|line| serde_json::from_str(&line).map_err(|e| e.to_string()))
+            {
+                Ok(record) => Some(record),
+                Err(e) => {
+                    eprintln!("skipping unreadable journal entry: {}", e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn replay(notes: &mut HashMap<String, Note>) -> usize {
+    let records = read();
+    let count = records.len();
+    for record in records {
+        apply_record(notes, record);
+    }
+    count
+}
+
+/// Merges the journal into a snapshot written by another program, note by
+/// note. A record only replaces the snapshot's copy of a note if it is newer,
+/// and a note the snapshot no longer has stays deleted unless the journal
+/// created it.
+pub fn merge(notes: &mut HashMap<String, Note>) -> usize {
+    merge_records(notes, read())
+}
+
+fn parse_time(value: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    chrono::DateTime::parse_from_rfc3339(value).ok()
+}
+
+fn merge_records(notes: &mut HashMap<String, Note>, records: Vec<JournalRecord>) -> usize {
+    let theirs: HashMap<String, Option<_>> = notes
+        .iter()
+        .map(|(id, note)| (id.clone(), parse_time(&note.updated_at)))
+        .collect();
+    let mut created = HashSet::new();
+    let mut merged = 0;
+    for record in records {
+        let keep = match theirs.get(record.id()) {
+            Some(Some(updated)) => parse_time(record.at()).is_none_or(|at| at > *updated),
+            Some(None) => true,
+            None => matches!(record, JournalRecord::Create { .. }) || created.contains(record.id()),
+        };
+        if !keep {
+            continue;
+        }
+        if let JournalRecord::Create { note, .. } = &record {
+            created.insert(note.id.clone());
+        }
+        apply_record(notes, record);
+        merged += 1;
+    }
+    merged
+}
+
+pub fn is_empty() -> bool {
+    fs::metadata(journal_path())
+        .map(|m| m.len() == 0)
+        .unwrap_or(true)
+}
+
+/// Empties the journal after its changes have been written to a snapshot.
+pub fn clear() -> io::Result<()> {
+    match fs::remove_file(journal_path()) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Whether the journal is large or old enough to fold into the snapshot.
+pub fn needs_compaction(records_since_snapshot: usize) -> bool {
+    if records_since_snapshot >= COMPACT_AFTER_RECORDS {
+        return true;
+    }
+    fs::metadata(journal_path())
+        .and_then(|m| m.created().or_else(|_| m.modified()))
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .map(|age| age >= COMPACT_AFTER)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, content: &str, updated_at: &str) -> Note {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "content": content,
+            "updated_at": updated_at,
+        }))
+        .unwrap()
+    }
+
+    fn update(at: &str, note: Note) -> JournalRecord {
+        JournalRecord::Update {
+            at: at.to_string(),
+            note,
+        }
+    }
+
+    #[test]
+    fn merge_keeps_the_newer_side_of_each_note() {
+        let mut notes: HashMap<String, Note> = [
+            note("theirs", "edited elsewhere", "2026-01-01T12:00:00+00:00"),
+            note("ours", "old", "2026-01-01T09:00:00+00:00"),
+        ]
+        .into_iter()
+        .map(|n| (n.id.clone(), n))
+        .collect();
+        let records = vec![
+            update(
+                "2026-01-01T10:00:00+00:00",
+                note("theirs", "stale local edit", "2026-01-01T10:00:00+00:00"),
+            ),
+            update(
+                "2026-01-01T11:00:00+00:00",
+                note("ours", "local edit", "2026-01-01T11:00:00+00:00"),
+            ),
+            update(
+                "2026-01-01T11:00:00+00:00",
+                note(
+                    "deleted",
+                    "edit to a note deleted elsewhere",
+                    "2026-01-01T11:00:00+00:00",
+                ),
+            ),
+            JournalRecord::Create {
+                at: "2026-01-01T11:30:00+00:00".to_string(),
+                note: note("new", "created here", "2026-01-01T11:30:00+00:00"),
+            },
+        ];
+
+        assert_eq!(merge_records(&mut notes, records), 2);
+        assert_eq!(notes["theirs"].content, "edited elsewhere");
+        assert_eq!(notes["ours"].content, "local edit");
+        assert_eq!(notes["new"].content, "created here");
+        assert!(!notes.contains_key("deleted"));
+    }
+}
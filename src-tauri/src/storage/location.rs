@@ -0,0 +1,474 @@
+//! Where the notes store lives on disk.
+//!
+//! In order of precedence the directory comes from the `--data-dir` flag, the
+//! `STICKYNOTES_DATA_DIR` environment variable, portable mode (a `portable`
+//! marker file next to the executable keeps everything in `data/` beside it),
+//! the `data_dir` setting, and finally the platform data directory.
+
+use crate::settings;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+pub const DATA_DIR_ENV: &str = "STICKYNOTES_DATA_DIR";
+const DATA_DIR_FLAG: &str = "--data-dir";
+const PORTABLE_MARKER: &str = "portable";
+
+/// Lives in the data directory in portable mode, so it never moves with it.
+const SETTINGS_FILE: &str = "settings.json";
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DataDirSource {
+    Cli,
+    Env,
+    Portable,
+    Settings,
+    Default,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DataDirInfo {
+    pub path: String,
+    pub source: DataDirSource,
+}
+
+static CURRENT: RwLock<Option<(PathBuf, DataDirSource)>> = RwLock::new(None);
+
+fn default_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("com.stickynotes.app")
+}
+
+/// The data directory used in portable mode, if the marker file is present.
+pub fn portable_dir() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let exe_dir = exe.parent()?;
+    exe_dir
+        .join(PORTABLE_MARKER)
+        .exists()
+        .then(|| exe_dir.join("data"))
+}
+
+fn cli_data_dir() -> Option<PathBuf> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == DATA_DIR_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(value) = arg
+            .strip_prefix(DATA_DIR_FLAG)
+            .and_then(|r| r.strip_prefix('='))
+        {
+            return Some(PathBuf::from(value));
+        }
+    }
+    None
+}
+
+fn resolve() -> (PathBuf, DataDirSource) {
+    if let Some(dir) = cli_data_dir() {
+        return (dir, DataDirSource::Cli);
+    }
+    if let Some(dir) = std::env::var_os(DATA_DIR_ENV).filter(|v| !v.is_empty()) {
+        return (PathBuf::from(dir), DataDirSource::Env);
+    }
+    if let Some(dir) = portable_dir() {
+        return (dir, DataDirSource::Portable);
+    }
+    if let Some(dir) = settings::current().data_dir {
+        return (dir, DataDirSource::Settings);
+    }
+    (default_dir(), DataDirSource::Default)
+}
+
+fn current() -> (PathBuf, DataDirSource) {
+    if let Some(current) = CURRENT.read().ok().and_then(|c| c.clone()) {
+        return current;
+    }
+    let resolved = resolve();
+    if let Ok(mut current) = CURRENT.write() {
+        *current = Some(resolved.clone());
+    }
+    resolved
+}
+
+/// A data and settings directory of its own for a test, so tests never touch
+/// the user's. Tests holding one run one at a time, and dropping it deletes
+/// the directory.
+#[cfg(test)]
+pub(crate) struct TestDir {
+    _dir: tempfile::TempDir,
+    _guard: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+pub(crate) fn test_dir() -> TestDir {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::Builder::new()
+        .prefix("stickynotes-test-")
+        .tempdir()
+        .unwrap();
+    if let Ok(mut current) = CURRENT.write() {
+        *current = Some((dir.path().join("data"), DataDirSource::Default));
+    }
+    settings::use_dir(Some(dir.path().join("config")));
+    TestDir {
+        _dir: dir,
+        _guard: guard,
+    }
+}
+
+#[cfg(test)]
+impl TestDir {
+    pub(crate) fn path(&self) -> &Path {
+        self._dir.path()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        if let Ok(mut current) = CURRENT.write() {
+            *current = None;
+        }
+        settings::use_dir(None);
+    }
+}
+
+pub fn storage_dir() -> PathBuf {
+    let (dir, _) = current();
+    fs::create_dir_all(&dir).ok();
+    dir
+}
+
+pub fn data_dir_info() -> DataDirInfo {
+    let (path, source) = current();
+    DataDirInfo {
+        path: path.to_string_lossy().into_owned(),
+        source,
+    }
+}
+
+/// Left behind when the store moves: settings stay where they are looked up,
+/// and the instance lock and socket belong to this process.
+const STAYS_PUT: &[&str] = &[SETTINGS_FILE, "instance.lock", "instance.sock"];
+
+#[derive(Clone, Copy, PartialEq)]
+struct Entry {
+    dir: bool,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+/// Every file and directory of the store under `dir`, keyed by path relative
+/// to it.
+fn list_tree(dir: &Path) -> io::Result<HashMap<PathBuf, Entry>> {
+    fn walk(root: &Path, rel: &Path, out: &mut HashMap<PathBuf, Entry>) -> io::Result<()> {
+        for entry in fs::read_dir(root.join(rel))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if rel.as_os_str().is_empty() && STAYS_PUT.iter().any(|n| name == *n) {
+                continue;
+            }
+            let path = rel.join(&name);
+            let meta = entry.metadata()?;
+            out.insert(
+                path.clone(),
+                Entry {
+                    dir: meta.is_dir(),
+                    len: if meta.is_dir() { 0 } else { meta.len() },
+                    modified: meta.modified().ok(),
+                },
+            );
+            if meta.is_dir() {
+                walk(root, &path, out)?;
+            }
+        }
+        Ok(())
+    }
+    let mut out = HashMap::new();
+    walk(dir, Path::new(""), &mut out)?;
+    Ok(out)
+}
+
+fn copy_entry(from: &Path, to: &Path, entry: &Entry) -> io::Result<()> {
+    if entry.dir {
+        return fs::create_dir_all(to);
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(from, to).map(|_| ())
+}
+
+/// A copy of the store in a new directory. It only becomes the active store
+/// when `commit` is called; dropped before that, the copy is removed again
+/// and the original is left as it was.
+pub struct StorageMove {
+    source: PathBuf,
+    target: PathBuf,
+    created_target: bool,
+    copied: HashMap<PathBuf, Entry>,
+    committed: bool,
+}
+
+/// Checks `target` and copies the store into it. Nothing is locked, so the
+/// app keeps saving meanwhile; `StorageMove::sync` catches up on that.
+/// Returns `None` when `target` already is the data directory.
+pub fn begin_move(target: &Path) -> Result<Option<StorageMove>, String> {
+    let (source_dir, source) = current();
+    match source {
+        DataDirSource::Cli | DataDirSource::Env | DataDirSource::Portable => {
+            return Err(format!(
+                "The data directory is fixed by {:?} and cannot be moved from the app",
+                source
+            ))
+        }
+        DataDirSource::Settings | DataDirSource::Default => {}
+    }
+
+    if !target.is_absolute() {
+        return Err("The new data directory must be an absolute path".to_string());
+    }
+    let source_dir = source_dir.canonicalize().unwrap_or(source_dir);
+    let created_target = !target.exists();
+    fs::create_dir_all(target)
+        .map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+    let target = target.canonicalize().map_err(|e| e.to_string())?;
+    if target == source_dir {
+        return Ok(None);
+    }
+    if target.starts_with(&source_dir) || source_dir.starts_with(&target) {
+        return Err(
+            "The new data directory cannot contain or be inside the current one".to_string(),
+        );
+    }
+    let not_empty = fs::read_dir(&target)
+        .map(|mut entries| entries.next().is_some())
+        .unwrap_or(false);
+    if not_empty {
+        return Err(format!("{} is not empty", target.display()));
+    }
+
+    let mut pending = StorageMove {
+        source: source_dir,
+        target,
+        created_target,
+        copied: HashMap::new(),
+        committed: false,
+    };
+    pending.sync_files()?;
+    Ok(Some(pending))
+}
+
+impl StorageMove {
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Copies whatever changed in the source since the last pass and removes
+    /// what has gone from it.
+    fn sync_files(&mut self) -> Result<(), String> {
+        let now = list_tree(&self.source).map_err(|e| e.to_string())?;
+        // Parents sort before their children.
+        let mut paths: Vec<&PathBuf> = now.keys().collect();
+        paths.sort();
+        for rel in paths {
+            let entry = &now[rel];
+            if self.copied.get(rel) == Some(entry) {
+                continue;
+            }
+            copy_entry(&self.source.join(rel), &self.target.join(rel), entry)
+                .map_err(|e| format!("Failed to copy {}: {}", rel.display(), e))?;
+        }
+        for (rel, entry) in &self.copied {
+            if now.contains_key(rel) {
+                continue;
+            }
+            let path = self.target.join(rel);
+            if entry.dir {
+                fs::remove_dir_all(&path).ok();
+            } else {
+                fs::remove_file(&path).ok();
+            }
+        }
+        self.copied = now;
+        Ok(())
+    }
+
+    /// Brings the copy up to date and checks it against the source. Call
+    /// with the writer closed, so nothing changes underneath.
+    pub fn sync(&mut self) -> Result<(), String> {
+        self.sync_files()?;
+        let copy = list_tree(&self.target).map_err(|e| e.to_string())?;
+        let matches = copy.len() == self.copied.len()
+            && self.copied.iter().all(|(rel, entry)| {
+                copy.get(rel)
+                    .map(|c| c.dir == entry.dir && c.len == entry.len)
+                    .unwrap_or(false)
+            });
+        if !matches {
+            return Err(format!(
+                "The copy in {} does not match the original",
+                self.target.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Makes the copy the active store and only then deletes the original.
+    /// Anything that cannot be deleted is left behind, not lost.
+    pub fn commit(mut self) -> PathBuf {
+        if let Ok(mut current) = CURRENT.write() {
+            *current = Some((self.target.clone(), DataDirSource::Settings));
+        }
+        self.committed = true;
+        let entries = self
+            .copied
+            .keys()
+            .filter(|rel| rel.components().count() == 1);
+        for rel in entries {
+            let path = self.source.join(rel);
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            };
+            if let Err(e) = removed {
+                eprintln!("failed to remove {} after moving it: {}", path.display(), e);
+            }
+        }
+        self.target.clone()
+    }
+}
+
+impl Drop for StorageMove {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        let removed = if self.created_target {
+            fs::remove_dir_all(&self.target)
+        } else {
+            fs::read_dir(&self.target).and_then(|entries| {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    let path = entry.path();
+                    if path.is_dir() {
+                        fs::remove_dir_all(&path)?;
+                    } else {
+                        fs::remove_file(&path)?;
+                    }
+                }
+                Ok(())
+            })
+        };
+        if let Err(e) = removed {
+            eprintln!(
+                "failed to remove partial copy in {}: {}",
+                self.target.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: PathBuf, data: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn fill_store() -> PathBuf {
+        let source = storage_dir();
+        write(source.join("notes.json"), "[]");
+        write(source.join("media").join("a.png"), "png");
+        write(source.join(SETTINGS_FILE), "{}");
+        source
+    }
+
+    fn assert_untouched(source: &Path) {
+        assert_eq!(storage_dir(), source);
+        assert_eq!(data_dir_info().source, DataDirSource::Default);
+        assert_eq!(settings::current().data_dir, None);
+        assert_eq!(read(source.join("notes.json")), "[]");
+        assert_eq!(read(source.join("media").join("a.png")), "png");
+    }
+
+    #[test]
+    fn moves_the_store_and_catches_up_on_changes() {
+        let dir = test_dir();
+        let source = fill_store();
+        let target = dir.path().join("moved");
+        let mut pending = begin_move(&target).unwrap().unwrap();
+        assert_eq!(read(target.join("notes.json")), "[]");
+        assert!(!target.join(SETTINGS_FILE).exists());
+
+        write(source.join("notes.json"), r#"[{"id":"a"}]"#);
+        write(source.join("media").join("b.png"), "png");
+        fs::remove_file(source.join("media").join("a.png")).unwrap();
+        pending.sync().unwrap();
+        let moved = pending.commit();
+
+        assert_eq!(moved, target.canonicalize().unwrap());
+        assert_eq!(storage_dir(), moved);
+        assert_eq!(read(moved.join("notes.json")), r#"[{"id":"a"}]"#);
+        assert!(moved.join("media").join("b.png").is_file());
+        assert!(!moved.join("media").join("a.png").exists());
+        assert!(!source.join("notes.json").exists() && !source.join("media").exists());
+        assert!(source.join(SETTINGS_FILE).is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_copy_leaves_the_source_in_place() {
+        let dir = test_dir();
+        let source = fill_store();
+        std::os::unix::fs::symlink(dir.path().join("missing"), source.join("dangling")).unwrap();
+        let target = dir.path().join("moved");
+
+        let err = begin_move(&target).err().unwrap();
+        assert!(err.contains("Failed to copy dangling"), "{}", err);
+        assert!(!target.exists());
+        assert_untouched(&source);
+    }
+
+    #[test]
+    fn failed_verify_empties_the_copy_again() {
+        let dir = test_dir();
+        let source = fill_store();
+        let target = dir.path().join("moved");
+        fs::create_dir(&target).unwrap();
+        let mut pending = begin_move(&target).unwrap().unwrap();
+
+        write(target.join("notes.json"), "[] ");
+        let err = pending.sync().unwrap_err();
+        assert!(err.contains("does not match"), "{}", err);
+        drop(pending);
+
+        assert_eq!(fs::read_dir(&target).unwrap().count(), 0);
+        assert_untouched(&source);
+    }
+
+    #[test]
+    fn refuses_targets_inside_the_store() {
+        let _dir = test_dir();
+        let source = fill_store();
+        assert!(begin_move(&source.join("media")).is_err());
+        assert!(begin_move(&source).unwrap().is_none());
+        assert_untouched(&source);
+    }
+}
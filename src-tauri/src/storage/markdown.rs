@@ -0,0 +1,356 @@
+use super::crypto;
+use super::repository::NoteRepository;
+use super::write_atomic;
+use crate::notes::Note;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// html2md drops <u>, so underline is carried through as placeholders and
+// written back as inline HTML, which CommonMark passes through untouched.
+const UNDERLINE_OPEN: &str = "\u{E000}";
+const UNDERLINE_CLOSE: &str = "\u{E001}";
+// Attachment chips would come back as plain links, losing the attributes
+// that make them chips, so each one is swapped for a numbered placeholder
+// and written back as the original inline HTML.
+const ATTACHMENT_OPEN: char = '\u{E002}';
+const ATTACHMENT_CLOSE: char = '\u{E003}';
+
+/// One `<id>.md` file per note, with the note's metadata in YAML frontmatter
+/// and its content converted from the editor's HTML to Markdown.
+pub struct MarkdownRepository {
+    dir: PathBuf,
+}
+
+impl MarkdownRepository {
+    pub fn open(dir: &Path) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create notes directory: {}", e))?;
+        Ok(MarkdownRepository {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn path_for(&self, id: &str) -> Result<PathBuf, String> {
+        if id.is_empty() || id.contains(['/', '\\']) || id.starts_with('.') {
+            return Err(format!("Invalid note id {:?}", id));
+        }
+        Ok(self.dir.join(format!("{}.md", id)))
+    }
+}
+
+/// Replaces every `<a data-attachment ...>...</a>` in `html` with a
+/// placeholder, returning the new HTML and the anchors in order.
+fn take_attachments(html: &str) -> (String, Vec<String>) {
+    let mut out = String::with_capacity(html.len());
+    let mut anchors = Vec::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<a ") {
+        let tag_end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let is_chip = rest[start..tag_end].contains("data-attachment");
+        let close = rest[tag_end..].find("</a>").map(|i| tag_end + i + 4);
+        match close {
+            Some(end) if is_chip => {
+                out.push_str(&rest[..start]);
+                out.push(ATTACHMENT_OPEN);
+                out.push_str(&anchors.len().to_string());
+                out.push(ATTACHMENT_CLOSE);
+                // The tag is passed through as HTML, but the chip's text
+                // would still be read as Markdown.
+                let text: String = rest[tag_end + 1..end - 4]
+                    .chars()
+                    .map(|c| match c {
+                        '*' | '_' | '[' | ']' | '`' | '~' | '\\' => format!("&#{};", c as u32),
+                        c => c.to_string(),
+                    })
+                    .collect();
+                anchors.push(format!("{}{}</a>", &rest[start..=tag_end], text));
+                rest = &rest[end..];
+            }
+            _ => {
+                out.push_str(&rest[..=tag_end]);
+                rest = &rest[tag_end + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    (out, anchors)
+}
+
+pub fn html_to_markdown(html: &str) -> String {
+    let (html, anchors) = take_attachments(html);
+    let html = html
+        .replace("<u>", UNDERLINE_OPEN)
+        .replace("</u>", UNDERLINE_CLOSE);
+    let mut markdown = html2md::parse_html(&html)
+        .replace(UNDERLINE_OPEN, "<u>")
+        .replace(UNDERLINE_CLOSE, "</u>");
+    for (i, anchor) in anchors.iter().enumerate() {
+        let placeholder = format!("{}{}{}", ATTACHMENT_OPEN, i, ATTACHMENT_CLOSE);
+        markdown = markdown.replacen(&placeholder, anchor, 1);
+    }
+    markdown
+}
+
+pub fn markdown_to_html(markdown: &str) -> String {
+    let parser =
+        pulldown_cmark::Parser::new_ext(markdown, pulldown_cmark::Options::ENABLE_STRIKETHROUGH);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
+const FRONTMATTER_KEYS: &[&str] = &[
+    "id",
+    "bg_color",
+    "fg_color",
+    "x",
+    "y",
+    "width",
+    "height",
+    "window_level",
+    "created_at",
+    "updated_at",
+    "lock",
+    "deleted_at",
+];
+
+fn render(note: &Note) -> Result<String, String> {
+    let value = serde_json::to_value(note).map_err(|e| e.to_string())?;
+    let mut out = String::from("---\n");
+    for key in FRONTMATTER_KEYS {
+        if let Some(v) = value.get(*key) {
+            // JSON strings, numbers and single-line objects are valid YAML
+            // as-is.
+            out.push_str(&format!("{}: {}\n", key, v));
+        }
+    }
+    out.push_str("---\n\n");
+    out.push_str(&html_to_markdown(&note.content));
+    out.push('\n');
+    Ok(out)
+}
+
+fn parse_scalar(raw: &str) -> Value {
+    let raw = raw.trim();
+    if raw.starts_with('{') {
+        if let Ok(value) = serde_json::from_str::<Value>(raw) {
+            return value;
+        }
+    }
+    if raw.starts_with('"') {
+        if let Ok(s) = serde_json::from_str::<String>(raw) {
+            return Value::String(s);
+        }
+    } else if let Some(inner) = raw.strip_prefix('\'').and_then(|r| r.strip_suffix('\'')) {
+        return Value::String(inner.replace("''", "'"));
+    }
+    match raw.parse::<f64>() {
+        Ok(n) => Value::from(n),
+        Err(_) => Value::String(raw.to_string()),
+    }
+}
+
+/// Decrypts a note file if needed; encrypted stores are not human-readable.
+fn decode(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(crypto::open(bytes)?).map_err(|e| e.to_string())
+}
+
+fn parse(data: &str, fallback_id: &str) -> Result<Note, String> {
+    let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+    let (frontmatter, body) = match data
+        .strip_prefix("---\n")
+        .or_else(|| data.strip_prefix("---\r\n"))
+    {
+        Some(rest) => {
+            let end = rest
+                .find("\n---")
+                .ok_or_else(|| "Unterminated frontmatter".to_string())?;
+            let body = rest[end + 4..].trim_start_matches(['\r', '\n']);
+            (&rest[..end], body)
+        }
+        None => ("", data),
+    };
+
+    let mut fields = Map::new();
+    for line in frontmatter.lines() {
+        if let Some((key, raw)) = line.split_once(':') {
+            fields.insert(key.trim().to_string(), parse_scalar(raw));
+        }
+    }
+    fields
+        .entry("id")
+        .or_insert_with(|| Value::String(fallback_id.to_string()));
+    fields.insert("content".to_string(), Value::String(markdown_to_html(body)));
+
+    serde_json::from_value(Value::Object(fields)).map_err(|e| e.to_string())
+}
+
+impl NoteRepository for MarkdownRepository {
+    fn get(&self, id: &str) -> Result<Option<Note>, String> {
+        match fs::read(self.path_for(id)?) {
+            Ok(bytes) => parse(&decode(&bytes)?, id).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn put(&mut self, note: &Note) -> Result<(), String> {
+        let data = crypto::seal(render(note)?.as_bytes())?;
+        write_atomic(&self.path_for(&note.id)?, &data)
+            .map_err(|e| format!("Failed to save note: {}", e))
+    }
+
+    fn delete(&mut self, id: &str) -> Result<(), String> {
+        match fs::remove_file(self.path_for(id)?) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to delete note: {}", e)),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<Note>, String> {
+        let entries = fs::read_dir(&self.dir).map_err(|e| e.to_string())?;
+        let mut notes = Vec::new();
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("md") {
+                continue;
+            }
+            let stem = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string();
+            match fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| decode(&bytes))
+                .and_then(|d| parse(&d, &stem))
+            {
+                Ok(note) => notes.push(note),
+                Err(e) => eprintln!("skipping unreadable note {}: {}", path.display(), e),
+            }
+        }
+        Ok(notes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notes::{NoteLock, WindowLevel};
+    use crate::storage::crypto::KdfParams;
+
+    fn round_trip(note: &Note) -> Note {
+        parse(&render(note).unwrap(), "fallback").unwrap()
+    }
+
+    fn json(note: &Note) -> Value {
+        serde_json::to_value(note).unwrap()
+    }
+
+    #[test]
+    fn round_trips_every_frontmatter_field() {
+        let note = Note {
+            id: "a1b2".to_string(),
+            content: "<p>Hello <strong>bold</strong> and <em>it's</em></p>\n".to_string(),
+            bg_color: "#ffeeaa".to_string(),
+            fg_color: "#112233".to_string(),
+            x: 12.5,
+            y: -40.0,
+            width: 333.0,
+            height: 210.25,
+            window_level: WindowLevel::AlwaysOnTop,
+            created_at: "2024-01-02T03:04:05.678+00:00".to_string(),
+            updated_at: "2024-02-03T04:05:06+00:00".to_string(),
+            lock: None,
+            deleted_at: Some("2024-03-04T05:06:07+00:00".to_string()),
+        };
+        assert_eq!(json(&round_trip(&note)), json(&note));
+    }
+
+    #[test]
+    fn round_trips_a_locked_note() {
+        let note = Note {
+            content: String::new(),
+            lock: Some(NoteLock {
+                kdf: KdfParams {
+                    salt: "c2FsdA==".to_string(),
+                    m_cost: 19456,
+                    t_cost: 2,
+                    p_cost: 1,
+                    verifier: "dmVyaWZpZXI=".to_string(),
+                },
+                content: "U05FTkMxAA==".to_string(),
+                media: vec!["abc.png".to_string()],
+                unlocked: false,
+            }),
+            ..Note::new()
+        };
+        let back = round_trip(&note);
+        assert_eq!(json(&back), json(&note));
+        assert_eq!(back.lock.unwrap().media, ["abc.png"]);
+    }
+
+    #[test]
+    fn keeps_underline_and_images() {
+        let html = concat!(
+            "<p>Plain <u>underlined</u> text</p>\n",
+            r#"<p><img src="http://media.localhost/abc.png?size=display" alt="" /></p>"#,
+            "\n",
+        );
+        let markdown = html_to_markdown(html);
+        assert!(markdown.contains("<u>underlined</u>"), "{}", markdown);
+        let back = markdown_to_html(&markdown);
+        assert!(back.contains("<u>underlined</u>"), "{}", back);
+        assert!(
+            back.contains(r#"<img src="http://media.localhost/abc.png?size=display""#),
+            "{}",
+            back
+        );
+        assert_eq!(html_to_markdown(&back), markdown);
+    }
+
+    #[test]
+    fn reads_a_file_without_frontmatter() {
+        let note = parse("# Title\n\nSome *text*.\n", "from-name").unwrap();
+        assert_eq!(note.id, "from-name");
+        assert_eq!(note.content, "<h1>Title</h1>\n<p>Some <em>text</em>.</p>\n");
+        assert_eq!(note.window_level, WindowLevel::Normal);
+        assert!(note.lock.is_none() && note.deleted_at.is_none());
+    }
+
+    #[test]
+    fn parses_hand_written_scalars() {
+        assert_eq!(parse_scalar(r#" "a \"b\"" "#), Value::from(r#"a "b""#));
+        assert_eq!(parse_scalar("'it''s'"), Value::from("it's"));
+        assert_eq!(parse_scalar("12.5"), Value::from(12.5));
+        assert_eq!(parse_scalar("#ffeeaa"), Value::from("#ffeeaa"));
+        assert_eq!(parse_scalar(r#"{"a": 1}"#), serde_json::json!({"a": 1}));
+        assert!(parse("---\nid: \"x\"\n", "x").is_err());
+    }
+
+    #[test]
+    fn keeps_attachment_chips() {
+        let chip = concat!(
+            r#"<a href="http://media.localhost/abc.pdf" data-name="report_v2 *final*.pdf" "#,
+            r#"data-size="12345" data-attachment="" class="attachment-chip">"#,
+            "report_v2 *final*.pdf · 12 KB</a>",
+        );
+        let html = format!(
+            r#"<p>See {} and <a href="https://example.com">this</a>.</p>"#,
+            chip
+        );
+        let markdown = html_to_markdown(&html);
+        assert!(
+            markdown.contains("[this](https://example.com)"),
+            "{}",
+            markdown
+        );
+        let back = markdown_to_html(&markdown);
+        assert_eq!(back.trim(), html);
+        assert_eq!(html_to_markdown(&back), markdown);
+    }
+}
@@ -0,0 +1,831 @@
+//! Pasted images live in `media/` and are referenced from note content as
+//! `http://media.localhost/<file>`. Nothing removes them when a note or an
+//! image is deleted, so unreferenced files are collected here: first moved
+//! to `media-quarantine/`, then purged once they have sat there for the
+//! grace period. A quarantined file that is referenced again is restored.
+//!
+//! Files are named after the SHA-256 of their contents, so pasting the same
+//! image twice stores it once. With encryption on, the name is an HMAC under
+//! the store key instead, so the listing does not show whether a known file
+//! is present; turning encryption on or off renames everything. Files from
+//! before content names were named with a random UUID; they are renamed on
+//! load. `.aliases.json`, sealed like the rest, maps old names to new ones so
+//! existing note content keeps working.
+//!
+//! Downscaled copies live in `media/variants/` as `<hash>.<variant>.<ext>`.
+//! They are made on a background thread when an image is saved, or on first
+//! request for images saved before variants existed; until then the original
+//! is served. They are dropped once their original is.
+//! The same goes for attachment metadata in `media/meta/`.
+
+use super::crypto::{self, Key};
+use super::image::{ImageFormat, Variant};
+use super::{media_dir, workspace_dir, write_atomic};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MEDIA_URL_PREFIX: &str = "media.localhost/";
+// An image is written before the note that shows it is saved; leave new
+// files alone so a collection in between cannot take them.
+const MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+// A dotfile, so garbage collection leaves it alone. Re-encryption does too;
+// `rename_for_key` rewrites it instead.
+const ALIASES_FILE: &str = ".aliases.json";
+// Partial copies made by `store_stream`.
+const IMPORT_TMP_PREFIX: &str = ".import-";
+
+fn quarantine_dir() -> PathBuf {
+    workspace_dir().join("media-quarantine")
+}
+
+fn variants_dir() -> PathBuf {
+    media_dir().join("variants")
+}
+
+/// Sidecar metadata for attachments, see `attachment.rs`.
+pub(super) fn meta_dir() -> PathBuf {
+    media_dir().join("meta")
+}
+
+fn aliases_path() -> PathBuf {
+    media_dir().join(ALIASES_FILE)
+}
+
+fn read_aliases(path: &Path, key: Option<&Key>) -> Option<HashMap<String, String>> {
+    let data = crypto::open_with(key, &fs::read(path).ok()?).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+fn write_aliases(
+    path: &Path,
+    key: Option<&Key>,
+    aliases: &HashMap<String, String>,
+) -> Result<(), String> {
+    let data = serde_json::to_vec_pretty(aliases).map_err(|e| e.to_string())?;
+    write_atomic(path, &crypto::seal_with(key, &data)?)
+        .map_err(|e| format!("Failed to write media aliases: {}", e))
+}
+
+/// Old file names mapped to the content-addressed names that replaced them.
+fn load_aliases() -> HashMap<String, String> {
+    read_aliases(&aliases_path(), crypto::current_key().as_ref()).unwrap_or_default()
+}
+
+fn save_aliases(aliases: &HashMap<String, String>) -> Result<(), String> {
+    write_aliases(&aliases_path(), crypto::current_key().as_ref(), aliases)
+}
+
+/// The content-addressed file name for `bytes`.
+pub fn content_name(bytes: &[u8], ext: &str) -> String {
+    content_name_with(crypto::current_key().as_ref(), bytes, ext)
+}
+
+fn content_name_with(key: Option<&Key>, bytes: &[u8], ext: &str) -> String {
+    match key {
+        Some(key) => format!("{}.{}", crypto::keyed_digest(key, bytes), ext),
+        None => format!("{:x}.{}", Sha256::digest(bytes), ext),
+    }
+}
+
+/// Stores `bytes` under their content name, reusing an existing copy.
+pub fn store(bytes: &[u8], ext: &str) -> Result<String, String> {
+    let filename = content_name(bytes, ext);
+    let path = media_dir().join(&filename);
+    if path.is_file() {
+        // Touch it so garbage collection treats it as freshly pasted until
+        // the note that uses it has been saved.
+        File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(SystemTime::now()))
+            .map_err(|e| format!("Failed to reuse image: {}", e))?;
+        return Ok(filename);
+    }
+    write_atomic(&path, &crypto::seal(bytes)?)
+        .map_err(|e| format!("Failed to write image: {}", e))?;
+    // Only images get variants; the original is saved either way.
+    if Variant::ALL
+        .iter()
+        .any(|&v| variant_name(&filename, v).is_some())
+    {
+        make_variants(&filename, variants_dir(), Some(bytes.to_vec()), path);
+    }
+    Ok(filename)
+}
+
+/// Like `store`, but copies from `reader` without holding the whole file in
+/// memory. `head` is what the caller already read from it to check the
+/// content. Encrypted stores still read everything, since a file is sealed
+/// in one piece.
+pub fn store_stream(head: &[u8], mut reader: impl Read, ext: &str) -> Result<String, String> {
+    if crypto::current_key().is_some() {
+        let mut bytes = head.to_vec();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        return store(&bytes, ext);
+    }
+
+    let dir = media_dir();
+    let tmp_path = dir.join(format!("{}{}.tmp", IMPORT_TMP_PREFIX, uuid::Uuid::new_v4()));
+    let copied = (|| {
+        let mut hasher = Sha256::new();
+        let mut out = File::create(&tmp_path)?;
+        hasher.update(head);
+        out.write_all(head)?;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            out.write_all(&buf[..n])?;
+        }
+        out.sync_all()?;
+        Ok::<_, std::io::Error>(format!("{:x}.{}", hasher.finalize(), ext))
+    })();
+    let filename = match copied {
+        Ok(filename) => filename,
+        Err(e) => {
+            fs::remove_file(&tmp_path).ok();
+            return Err(format!("Failed to copy file: {}", e));
+        }
+    };
+
+    let path = dir.join(&filename);
+    if path.is_file() {
+        fs::remove_file(&tmp_path).ok();
+        File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(SystemTime::now()))
+            .map_err(|e| format!("Failed to reuse file: {}", e))?;
+    } else {
+        fs::rename(&tmp_path, &path).map_err(|e| {
+            fs::remove_file(&tmp_path).ok();
+            format!("Failed to store file: {}", e)
+        })?;
+    }
+    Ok(filename)
+}
+
+fn variant_name(name: &str, variant: Variant) -> Option<String> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    let format = ImageFormat::from_ext(ext)?.variant_format()?;
+    Some(format!("{}.{}.{}", stem, variant.name(), format.ext()))
+}
+
+/// Writes the `variant` copy of `name` into `dir` if it needs one, and
+/// returns whether it did.
+fn write_variant(name: &str, bytes: &[u8], variant: Variant, dir: &Path) -> Result<bool, String> {
+    let variant_name = match variant_name(name, variant) {
+        Some(variant_name) => variant_name,
+        None => return Ok(false),
+    };
+    let format = match name
+        .rsplit_once('.')
+        .and_then(|(_, ext)| ImageFormat::from_ext(ext))
+    {
+        Some(format) => format,
+        None => return Ok(false),
+    };
+    let scaled = match super::image::downscale(bytes, format, variant)? {
+        Some(scaled) => scaled,
+        None => return Ok(false),
+    };
+    fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    write_atomic(&dir.join(variant_name), &crypto::seal(&scaled)?).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Images whose variants are being made.
+static MAKING_VARIANTS: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+/// Variants that were not made, because the image is too small to need them
+/// or could not be read or scaled. The original is served instead until the
+/// app restarts, rather than trying again on every request.
+static NO_VARIANTS: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+fn set_contains(set: &Mutex<Option<HashSet<String>>>, name: &str) -> bool {
+    set.lock()
+        .map(|names| names.as_ref().is_some_and(|names| names.contains(name)))
+        .unwrap_or(false)
+}
+
+/// Adds `name` to `set`, returning whether it was not there yet.
+fn set_insert(set: &Mutex<Option<HashSet<String>>>, name: &str) -> bool {
+    set.lock()
+        .map(|mut names| {
+            names
+                .get_or_insert_with(HashSet::new)
+                .insert(name.to_string())
+        })
+        .unwrap_or(false)
+}
+
+fn set_remove(set: &Mutex<Option<HashSet<String>>>, name: &str) {
+    if let Ok(mut names) = set.lock() {
+        if let Some(names) = names.as_mut() {
+            names.remove(name);
+        }
+    }
+}
+
+/// Makes the missing variants of `name` on a background thread, so that
+/// decoding and resizing a large image never holds up a paste or a request.
+/// `bytes` is the decrypted original if the caller has it; otherwise it is
+/// read from `path`. Does nothing if a thread is already at it.
+fn make_variants(name: &str, dir: PathBuf, bytes: Option<Vec<u8>>, path: PathBuf) {
+    if !set_insert(&MAKING_VARIANTS, name) {
+        return;
+    }
+    let name = name.to_string();
+    std::thread::spawn(move || {
+        let bytes = bytes.or_else(|| {
+            let data = fs::read(&path).ok()?;
+            crypto::open(&data).ok()
+        });
+        for variant in Variant::ALL {
+            let Some(file) = variant_name(&name, variant) else {
+                continue;
+            };
+            let made = match &bytes {
+                Some(bytes) => write_variant(&name, bytes, variant, &dir),
+                None => Err("the original could not be read".to_string()),
+            };
+            match made {
+                Ok(true) => {}
+                Ok(false) => {
+                    set_insert(&NO_VARIANTS, &file);
+                }
+                Err(e) => {
+                    set_insert(&NO_VARIANTS, &file);
+                    eprintln!(
+                        "failed to create {} variant of {}: {}",
+                        variant.name(),
+                        name,
+                        e
+                    );
+                }
+            }
+        }
+        set_remove(&MAKING_VARIANTS, &name);
+    });
+}
+
+/// Which file to serve for a downscaled copy of an image.
+pub enum VariantFile {
+    Ready(PathBuf),
+    /// The image is small enough, not a kind that gets variants, or the
+    /// copy could not be made.
+    Original,
+    /// The copy is still being made; serve the original for now, but do
+    /// not let it be cached as the variant.
+    Pending,
+}
+
+/// The file to serve for `variant` of the original at `path`. A missing
+/// variant is made in the background rather than on the calling thread.
+pub fn variant_path(path: &Path, variant: Variant) -> VariantFile {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return VariantFile::Original;
+    };
+    let Some(file) = variant_name(name, variant) else {
+        return VariantFile::Original;
+    };
+    let dir = variants_dir();
+    if dir.join(&file).is_file() {
+        return VariantFile::Ready(dir.join(file));
+    }
+    if set_contains(&NO_VARIANTS, &file) {
+        return VariantFile::Original;
+    }
+    make_variants(name, dir, None, path.to_path_buf());
+    VariantFile::Pending
+}
+
+/// Deletes variants and attachment metadata whose original is no longer in
+/// `media/` or quarantine.
+fn remove_orphaned_derivatives(report: &mut MediaGcReport) {
+    let stem = |name: &str| name.split('.').next().unwrap_or_default().to_string();
+    let mut originals: HashSet<String> = HashSet::new();
+    for dir in [media_dir(), quarantine_dir()] {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let name = parse_quarantined(&name).map_or(name.as_str(), |(_, n)| n);
+                originals.insert(stem(name));
+            }
+        }
+    }
+    let entries = [variants_dir(), meta_dir()]
+        .into_iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || originals.contains(&stem(&name)) {
+            continue;
+        }
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        if fs::remove_file(entry.path()).is_ok() {
+            report.bytes_reclaimed += size;
+        }
+    }
+}
+
+/// Why a media file name could not be served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupError {
+    /// Not a plain file name: empty, `..`, a path or an absolute path.
+    Invalid,
+    NotFound,
+    /// Resolves to somewhere outside the media directory, e.g. a symlink.
+    OutsideMediaDir,
+}
+
+/// True for a single, ordinary file name. Media is stored flat, so anything
+/// with a separator, a drive prefix or a `..` is refused before it gets near
+/// the file system.
+pub(super) fn is_plain_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\', ':'])
+        && !name.chars().any(|c| c.is_control())
+        && matches!(
+            Path::new(name).components().collect::<Vec<_>>().as_slice(),
+            [Component::Normal(_)]
+        )
+}
+
+/// Where a media URL's file name lives on disk, following aliases left by
+/// the migration to content-addressed names. The result is canonicalized
+/// and guaranteed to be inside the media directory.
+pub fn resolve(name: &str) -> Result<PathBuf, LookupError> {
+    resolve_in(&media_dir(), name)
+}
+
+/// Like `resolve`, for the media directory `dir` of any workspace.
+pub(super) fn resolve_in(dir: &Path, name: &str) -> Result<PathBuf, LookupError> {
+    if !is_plain_name(name) {
+        return Err(LookupError::Invalid);
+    }
+    let mut path = dir.join(name);
+    if !path.exists() {
+        let aliases = read_aliases(&dir.join(ALIASES_FILE), crypto::current_key().as_ref());
+        match aliases.unwrap_or_default().get(name) {
+            Some(target) if is_plain_name(target) => path = dir.join(target),
+            _ => return Err(LookupError::NotFound),
+        }
+    }
+    let dir = dir.canonicalize().map_err(|_| LookupError::NotFound)?;
+    let path = path.canonicalize().map_err(|_| LookupError::NotFound)?;
+    if !path.starts_with(&dir) || !path.is_file() {
+        return Err(LookupError::OutsideMediaDir);
+    }
+    Ok(path)
+}
+
+/// True for names of the form `<sha256>.<ext>`, which never change content.
+pub fn is_content_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default();
+    stem.len() == 64 && stem.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn is_uuid_name(name: &str) -> bool {
+    let stem = name.split_once('.').map_or(name, |(stem, _)| stem);
+    uuid::Uuid::parse_str(stem).is_ok()
+}
+
+/// Renames UUID-named media to content names and records the aliases.
+/// Identical files collapse into one. Returns how many files were renamed.
+pub fn migrate_to_content_names() -> Result<usize, String> {
+    let dir = media_dir();
+    let mut aliases = load_aliases();
+    let mut migrated = 0;
+    let entries = fs::read_dir(&dir).map_err(|e| e.to_string())?;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        if !path.is_file() || !is_uuid_name(&name) {
+            continue;
+        }
+        let data = fs::read(&path).map_err(|e| e.to_string())?;
+        let bytes = crypto::open(&data)?;
+        let ext = Path::new(&name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("png");
+        let target = content_name(&bytes, ext);
+        // Record the alias first: a crash after this point leaves either the
+        // old file or the new one reachable.
+        aliases.insert(name.clone(), target.clone());
+        save_aliases(&aliases)?;
+        if dir.join(&target).exists() {
+            fs::remove_file(&path)
+        } else {
+            fs::rename(&path, dir.join(&target))
+        }
+        .map_err(|e| format!("Failed to migrate {}: {}", name, e))?;
+        migrated += 1;
+    }
+    Ok(migrated)
+}
+
+/// Renames the media of the workspace in `workspace` to the names `new`
+/// gives them, once its files have been re-encrypted from `old` to `new`.
+/// Variants, attachment metadata and quarantined files follow, and aliases
+/// keep the old names working. Returns how many files were renamed.
+pub fn rename_for_key(
+    workspace: &Path,
+    old: Option<&Key>,
+    new: Option<&Key>,
+) -> Result<usize, String> {
+    let media = workspace.join("media");
+    let quarantine = workspace.join("media-quarantine");
+    let mut renamed: HashMap<String, String> = HashMap::new();
+
+    let mut rename = |path: &Path, name: &str, prefix: &str| -> Result<(), String> {
+        let bytes = crypto::open_with(new, &fs::read(path).map_err(|e| e.to_string())?)?;
+        let ext = name.rsplit_once('.').map_or("bin", |(_, ext)| ext);
+        let target = content_name_with(new, &bytes, ext);
+        if target == name {
+            return Ok(());
+        }
+        let target_path = path.with_file_name(format!("{}{}", prefix, target));
+        if target_path.exists() {
+            fs::remove_file(path)
+        } else {
+            fs::rename(path, &target_path)
+        }
+        .map_err(|e| format!("Failed to rename {}: {}", name, e))?;
+        renamed.insert(name.to_string(), target);
+        Ok(())
+    };
+    for entry in fs::read_dir(&media)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+    {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.path().is_file() && is_content_name(&name) {
+            rename(&entry.path(), &name, "")?;
+        }
+    }
+    for entry in fs::read_dir(&quarantine)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok())
+    {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some((stamp, original)) = parse_quarantined(&name) {
+            if is_content_name(original) {
+                rename(&entry.path(), original, &format!("{}-", stamp))?;
+            }
+        }
+    }
+
+    // Derivatives are named after their original's stem.
+    let stem = |name: &str| name.split('.').next().unwrap_or_default().to_string();
+    let stems: HashMap<String, String> = renamed
+        .iter()
+        .map(|(old, new)| (stem(old), stem(new)))
+        .collect();
+    for dir in [media.join("variants"), media.join("meta")] {
+        for entry in fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+        {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(new_stem) = stems.get(&stem(&name)) else {
+                continue;
+            };
+            let target = dir.join(format!("{}{}", new_stem, &name[stem(&name).len()..]));
+            if target.exists() {
+                fs::remove_file(entry.path()).ok();
+            } else if let Err(e) = fs::rename(entry.path(), &target) {
+                // Variants are made again on request; metadata only names
+                // the download.
+                eprintln!("failed to rename {}: {}", name, e);
+            }
+        }
+    }
+
+    // Aliases sit outside the re-encryption, so they are read with either
+    // key and always written back under the new one.
+    let path = media.join(ALIASES_FILE);
+    let mut aliases = read_aliases(&path, old)
+        .or_else(|| read_aliases(&path, new))
+        .unwrap_or_default();
+    for target in aliases.values_mut() {
+        if let Some(renamed) = renamed.get(target.as_str()) {
+            *target = renamed.clone();
+        }
+    }
+    aliases.extend(renamed.iter().map(|(old, new)| (old.clone(), new.clone())));
+    aliases.retain(|old, new| old != new);
+    if path.exists() || !aliases.is_empty() {
+        write_aliases(&path, new, &aliases)?;
+    }
+    Ok(renamed.len())
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MediaGcReport {
+    /// Files moved to quarantine by this run.
+    pub quarantined: usize,
+    pub quarantined_bytes: u64,
+    /// Quarantined files that were referenced again and moved back.
+    pub restored: usize,
+    /// Files deleted after their grace period.
+    pub purged: usize,
+    pub bytes_reclaimed: u64,
+}
+
+/// Length of the file name at the start of `rest`, which follows a media URL
+/// prefix.
+fn reference_len(rest: &str) -> usize {
+    rest.find(|c: char| matches!(c, '"' | '\'' | '<' | '>' | ')' | '?' | '#') || c.is_whitespace())
+        .unwrap_or(rest.len())
+}
+
+/// File names of every media item referenced from `html`.
+pub fn media_references(html: &str) -> impl Iterator<Item = &str> {
+    html.match_indices(MEDIA_URL_PREFIX)
+        .filter_map(move |(i, _)| {
+            let rest = &html[i + MEDIA_URL_PREFIX.len()..];
+            let name = &rest[..reference_len(rest)];
+            (!name.is_empty()).then_some(name)
+        })
+}
+
+/// `html` with each referenced file name replaced by what `rename` returns
+/// for it. Names it returns `None` for are left alone.
+pub fn rewrite_references(html: &str, rename: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(i) = rest.find(MEDIA_URL_PREFIX) {
+        let start = i + MEDIA_URL_PREFIX.len();
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let name = &rest[..reference_len(rest)];
+        match rename(name).filter(|_| !name.is_empty()) {
+            Some(renamed) => out.push_str(&renamed),
+            None => out.push_str(name),
+        }
+        rest = &rest[name.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Makes each old name in `renamed` resolve to its new one, for content that
+/// still names the old files and cannot be rewritten, such as locked notes.
+pub(super) fn add_aliases(renamed: &HashMap<String, String>) -> Result<(), String> {
+    if renamed.is_empty() {
+        return Ok(());
+    }
+    let mut aliases = load_aliases();
+    aliases.extend(renamed.iter().map(|(old, new)| (old.clone(), new.clone())));
+    save_aliases(&aliases)
+}
+
+fn file_age(path: &Path) -> Option<Duration> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    SystemTime::now().duration_since(modified).ok()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Splits a quarantined file name into the time it was quarantined and its
+/// original name.
+fn parse_quarantined(name: &str) -> Option<(u64, &str)> {
+    let (stamp, original) = name.split_once('-')?;
+    Some((stamp.parse().ok()?, original))
+}
+
+/// Quarantines media files not in `referenced`, restores quarantined ones
+/// that are, and purges those quarantined longer than `grace`.
+pub fn collect_garbage(
+    referenced: &HashSet<String>,
+    grace: Duration,
+) -> Result<MediaGcReport, String> {
+    let media = workspace_dir().join("media");
+    let quarantine = quarantine_dir();
+    let mut report = MediaGcReport::default();
+
+    // Notes written before the migration still use the old names.
+    let aliases = load_aliases();
+    let referenced: HashSet<String> = referenced
+        .iter()
+        .map(|name| aliases.get(name).unwrap_or(name).clone())
+        .collect();
+
+    // Bring back anything that is in use again before deciding what to purge.
+    if let Ok(entries) = fs::read_dir(&quarantine) {
+        let now = unix_now();
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let (stamp, original) = match parse_quarantined(&name) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            let path = entry.path();
+            if referenced.contains(original) {
+                fs::create_dir_all(&media).map_err(|e| e.to_string())?;
+                fs::rename(&path, media.join(original))
+                    .map_err(|e| format!("Failed to restore {}: {}", original, e))?;
+                report.restored += 1;
+            } else if now.saturating_sub(stamp) >= grace.as_secs() {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                fs::remove_file(&path)
+                    .map_err(|e| format!("Failed to purge {}: {}", original, e))?;
+                report.purged += 1;
+                report.bytes_reclaimed += size;
+            }
+        }
+    }
+
+    let entries = fs::read_dir(&media).into_iter().flatten();
+    let stamp = unix_now();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let old = file_age(&path).is_some_and(|age| age >= MIN_AGE);
+        // Left behind by an import that was cut short.
+        if name.starts_with(IMPORT_TMP_PREFIX) && name.ends_with(".tmp") {
+            let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            if old && fs::remove_file(&path).is_ok() {
+                report.bytes_reclaimed += size;
+            }
+            continue;
+        }
+        if !path.is_file() || name.starts_with('.') || referenced.contains(&name) || !old {
+            continue;
+        }
+        fs::create_dir_all(&quarantine).map_err(|e| e.to_string())?;
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        fs::rename(&path, quarantine.join(format!("{}-{}", stamp, name)))
+            .map_err(|e| format!("Failed to quarantine {}: {}", name, e))?;
+        report.quarantined += 1;
+        report.quarantined_bytes += size;
+    }
+
+    remove_orphaned_derivatives(&mut report);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{storage_dir, test_dir, TestDir};
+
+    fn setup() -> (TestDir, PathBuf) {
+        let dir = test_dir();
+        fs::write(storage_dir().join("secret.txt"), b"secret").unwrap();
+        fs::write(media_dir().join("image.png"), b"png").unwrap();
+        (dir, media_dir())
+    }
+
+    #[test]
+    fn resolves_plain_names() {
+        let (_dir, media) = setup();
+        let path = resolve("image.png").unwrap();
+        assert_eq!(path, media.canonicalize().unwrap().join("image.png"));
+        assert_eq!(resolve("missing.png"), Err(LookupError::NotFound));
+    }
+
+    #[test]
+    fn rejects_parent_segments() {
+        let _dir = setup();
+        for name in [
+            "..",
+            "../secret.txt",
+            "../../etc/passwd",
+            "a/../../secret.txt",
+        ] {
+            assert_eq!(resolve(name), Err(LookupError::Invalid), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_absolute_and_nested_paths() {
+        let _dir = setup();
+        for name in [
+            "/etc/passwd",
+            "//etc/passwd",
+            "variants/image.png",
+            "..\\secret.txt",
+            "C:\\Windows\\win.ini",
+            "C:secret.txt",
+        ] {
+            assert_eq!(resolve(name), Err(LookupError::Invalid), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_empty_hidden_and_control_names() {
+        let _dir = setup();
+        for name in ["", ".", ".aliases.json", "image.png\0", "image\n.png"] {
+            assert_eq!(resolve(name), Err(LookupError::Invalid), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_aliases_that_escape() {
+        let (_dir, media) = setup();
+        let mut aliases = load_aliases();
+        aliases.insert("old.png".into(), "../secret.txt".into());
+        save_aliases(&aliases).unwrap();
+        assert_eq!(resolve("old.png"), Err(LookupError::NotFound));
+        assert!(media.join(ALIASES_FILE).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_symlinks_out_of_the_media_dir() {
+        let (_dir, media) = setup();
+        let link = media.join("link.png");
+        std::os::unix::fs::symlink(media.parent().unwrap().join("secret.txt"), &link).unwrap();
+        assert_eq!(resolve("link.png"), Err(LookupError::OutsideMediaDir));
+    }
+
+    #[test]
+    fn recognizes_content_names() {
+        let name = content_name(b"png", "png");
+        assert!(is_content_name(&name));
+        assert!(is_content_name(&name.replace(".png", ".thumb.png")));
+        assert!(!is_content_name("3f2b8c1e-9a4d-4e6f-8b7a-1c2d3e4f5a6b.png"));
+    }
+
+    #[test]
+    fn rewrites_only_renamed_references() {
+        let html = "<img src=\"http://media.localhost/a.png\"><a href=\"http://media.localhost/b.pdf?download\">b</a>";
+        let rewritten =
+            rewrite_references(html, |name| (name == "b.pdf").then(|| "c.pdf".to_string()));
+        assert_eq!(
+            rewritten,
+            "<img src=\"http://media.localhost/a.png\"><a href=\"http://media.localhost/c.pdf?download\">b</a>"
+        );
+        assert_eq!(
+            rewrite_references("media.localhost/", |_| Some("x".into())),
+            "media.localhost/"
+        );
+    }
+
+    #[test]
+    fn renames_media_when_the_key_changes() {
+        let workspace =
+            std::env::temp_dir().join(format!("stickynotes-rename-{}", uuid::Uuid::new_v4()));
+        let media = workspace.join("media");
+        fs::create_dir_all(media.join("variants")).unwrap();
+        let (key, _) = crypto::new_key("passphrase").unwrap();
+        let plain = content_name(b"png", "png");
+        let stem = plain.split('.').next().unwrap().to_string();
+        fs::write(
+            media.join(&plain),
+            crypto::seal_with(Some(&key), b"png").unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            media.join("variants").join(format!("{}.thumb.png", stem)),
+            b"",
+        )
+        .unwrap();
+
+        assert_eq!(rename_for_key(&workspace, None, Some(&key)).unwrap(), 1);
+        let keyed = content_name_with(Some(&key), b"png", "png");
+        let keyed_stem = keyed.split('.').next().unwrap();
+        assert_ne!(keyed, plain);
+        assert!(media.join(&keyed).is_file() && !media.join(&plain).exists());
+        assert!(media
+            .join("variants")
+            .join(format!("{}.thumb.png", keyed_stem))
+            .is_file());
+        let aliases = read_aliases(&media.join(ALIASES_FILE), Some(&key)).unwrap();
+        assert_eq!(aliases[&plain], keyed);
+
+        // Turning encryption off again restores the plain names.
+        fs::write(media.join(&keyed), b"png").unwrap();
+        assert_eq!(rename_for_key(&workspace, Some(&key), None).unwrap(), 1);
+        assert!(media.join(&plain).is_file());
+        let aliases = read_aliases(&media.join(ALIASES_FILE), None).unwrap();
+        assert_eq!(aliases[&keyed], plain);
+        assert!(!aliases.contains_key(&plain));
+
+        fs::remove_dir_all(&workspace).ok();
+    }
+}
@@ -0,0 +1,159 @@
+//! Upgrades older notes.json layouts to the current envelope format.
+//!
+//! Each entry in `MIGRATIONS` takes a document at version `i` and returns it
+//! at version `i + 1`. Migrations operate on raw JSON so they keep working
+//! after the `Note` struct itself has moved on.
+
+use serde_json::{json, Value};
+
+pub const CURRENT_VERSION: u64 = 1;
+
+const MIGRATIONS: &[fn(Value) -> Value] = &[v0_to_v1];
+
+/// Versions before the envelope were a bare array of notes.
+fn v0_to_v1(value: Value) -> Value {
+    json!({ "version": 1, "notes": value })
+}
+
+pub fn detect_version(value: &Value) -> Result<u64, String> {
+    match value {
+        Value::Array(_) => Ok(0),
+        Value::Object(map) => map
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| "notes.json has no version field".to_string()),
+        _ => Err("notes.json is neither an array nor an object".to_string()),
+    }
+}
+
+pub fn migrate(mut value: Value) -> Result<Value, String> {
+    let mut version = detect_version(&value)?;
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "notes.json was written by a newer version of the app (format v{}, this build reads up to v{})",
+            version, CURRENT_VERSION
+        ));
+    }
+    while version < CURRENT_VERSION {
+        value = MIGRATIONS[version as usize](value);
+        version += 1;
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notes::WindowLevel;
+    use crate::storage::{parse_store, recover_notes};
+
+    const V0: &str = include_str!("fixtures/notes-v0.json");
+    const V1: &str = include_str!("fixtures/notes-v1.json");
+
+    #[test]
+    fn detects_fixture_versions() {
+        let v0: Value = serde_json::from_str(V0).unwrap();
+        let v1: Value = serde_json::from_str(V1).unwrap();
+        assert_eq!(detect_version(&v0).unwrap(), 0);
+        assert_eq!(detect_version(&v1).unwrap(), 1);
+    }
+
+    #[test]
+    fn v0_bare_array_upgrades_without_loss() {
+        let notes = parse_store(V0).unwrap();
+        assert_eq!(notes.len(), 3);
+
+        let pinned = notes
+            .iter()
+            .find(|n| n.id == "3f2b8c1e-9a4d-4e6f-8b7a-1c2d3e4f5a6b")
+            .unwrap();
+        assert!(pinned.content.contains("<strong>9:30</strong>"));
+        assert_eq!(pinned.window_level, WindowLevel::AlwaysOnTop);
+        assert_eq!(pinned.x, 1180.0);
+        assert_eq!(pinned.created_at, "2025-01-14T08:12:40.118532+00:00");
+
+        let image = notes
+            .iter()
+            .find(|n| n.id == "a7c9e0d2-5b1f-4c3a-9e8d-7f6a5b4c3d2e")
+            .unwrap();
+        assert_eq!(image.bg_color, "#fff3a0");
+        assert_eq!(image.window_level, WindowLevel::Desktop);
+        assert!(image.content.contains("http://media.localhost/"));
+    }
+
+    #[test]
+    fn v0_and_v1_fixtures_load_identically() {
+        let v0 = serde_json::to_value(parse_store(V0).unwrap()).unwrap();
+        let v1 = serde_json::to_value(parse_store(V1).unwrap()).unwrap();
+        assert_eq!(v0, v1);
+    }
+
+    #[test]
+    fn migrate_is_a_no_op_at_current_version() {
+        let v1: Value = serde_json::from_str(V1).unwrap();
+        assert_eq!(migrate(v1.clone()).unwrap(), v1);
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let future = json!({ "version": CURRENT_VERSION + 1, "notes": [] });
+        assert!(migrate(future).is_err());
+    }
+
+    #[test]
+    fn missing_fields_fall_back_to_defaults() {
+        let sparse = r#"[{ "id": "sparse", "content": "<p>hi</p>" }]"#;
+        let notes = parse_store(sparse).unwrap();
+        assert_eq!(notes[0].id, "sparse");
+        assert_eq!(notes[0].content, "<p>hi</p>");
+        assert_eq!(notes[0].window_level, WindowLevel::Normal);
+        assert_eq!(notes[0].width, 320.0);
+    }
+
+    #[test]
+    fn entries_without_an_id_are_lost_not_renamed() {
+        let data = r#"[{ "id": "kept", "content": "<p>a</p>" }, { "content": "<p>b</p>" }]"#;
+        let err = parse_store(data).unwrap_err();
+
+        let loaded = recover_notes(data, err, "corrupt.json".into());
+        assert_eq!(loaded.notes.keys().collect::<Vec<_>>(), ["kept"]);
+        let lost = loaded.recovery.unwrap().lost;
+        assert_eq!(
+            (lost.len(), lost[0].index, lost[0].id.as_deref()),
+            (1, 1, None)
+        );
+        assert!(
+            lost[0].error.contains("missing field `id`"),
+            "{}",
+            lost[0].error
+        );
+    }
+
+    #[test]
+    fn truncated_v0_file_recovers_complete_entries() {
+        let cut = &V0[..V0.find("c1d2e3f4").unwrap()];
+        assert!(parse_store(cut).is_err());
+
+        let loaded = recover_notes(cut, "truncated".into(), "corrupt.json".into());
+        let report = loaded.recovery.unwrap();
+        assert_eq!(loaded.notes.len(), 2);
+        assert_eq!(report.recovered, 2);
+        assert!(report.truncated);
+    }
+
+    #[test]
+    fn bad_entry_in_v1_file_only_loses_that_note() {
+        let broken = V1.replacen("\"x\": 420.5", "\"x\": \"left\"", 1);
+        assert!(parse_store(&broken).is_err());
+
+        let loaded = recover_notes(&broken, "bad field".into(), "corrupt.json".into());
+        let report = loaded.recovery.unwrap();
+        assert_eq!(loaded.notes.len(), 2);
+        assert_eq!(report.lost.len(), 1);
+        assert_eq!(
+            report.lost[0].id.as_deref(),
+            Some("a7c9e0d2-5b1f-4c3a-9e8d-7f6a5b4c3d2e")
+        );
+        assert!(!report.truncated);
+    }
+}
@@ -0,0 +1,168 @@
+use super::journal;
+use super::{
+    load_notes, reload_notes, save_notes, snapshot_changed, snapshot_hold, snapshot_read_only,
+    LoadedNotes,
+};
+use crate::notes::Note;
+use std::collections::{HashMap, HashSet};
+
+/// A single pending write, keyed by note id in the writer queue.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Put(Box<Note>),
+    Delete(String),
+}
+
+/// Persistence for individual notes. `NotesStore` keeps its own in-memory
+/// copy for reads; the repository is only the durable side.
+pub trait NoteRepository: Send {
+    fn get(&self, id: &str) -> Result<Option<Note>, String>;
+    fn put(&mut self, note: &Note) -> Result<(), String>;
+    fn delete(&mut self, id: &str) -> Result<(), String>;
+    fn list(&self) -> Result<Vec<Note>, String>;
+
+    /// Applies a batch of coalesced changes. Backends that rewrite a whole
+    /// file should override this to write once per batch.
+    fn apply(&mut self, changes: &[Change]) -> Result<(), String> {
+        for change in changes {
+            match change {
+                Change::Put(note) => self.put(note)?,
+                Change::Delete(id) => self.delete(id)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops superseded data the backend may still keep on disk, such as
+    /// journal entries or freed database pages.
+    fn compact(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Makes the repository hold exactly `notes`.
+    fn replace_all(&mut self, notes: &[Note]) -> Result<(), String> {
+        let keep: HashSet<&str> = notes.iter().map(|n| n.id.as_str()).collect();
+        for existing in self.list()? {
+            if !keep.contains(existing.id.as_str()) {
+                self.delete(&existing.id)?;
+            }
+        }
+        for note in notes {
+            self.put(note)?;
+        }
+        Ok(())
+    }
+}
+
+/// The notes.json store. Changes are appended to the journal and folded
+/// into the snapshot from time to time, rather than rewriting the whole file
+/// on every edit.
+pub struct JsonRepository {
+    notes: HashMap<String, Note>,
+    records_since_snapshot: usize,
+}
+
+impl JsonRepository {
+    pub fn open() -> (Self, LoadedNotes) {
+        Self::from_loaded(load_notes())
+    }
+
+    /// Opens notes.json again after another program changed it.
+    pub fn reload() -> (Self, LoadedNotes) {
+        Self::from_loaded(reload_notes())
+    }
+
+    fn from_loaded(loaded: LoadedNotes) -> (Self, LoadedNotes) {
+        // The journal has already been folded in; start from a clean one.
+        if !journal::is_empty() && snapshot_hold().is_none() {
+            if let Err(e) = save_notes(&loaded.notes) {
+                eprintln!("failed to compact notes journal: {}", e);
+            }
+        }
+        let repo = JsonRepository {
+            notes: loaded.notes.clone(),
+            records_since_snapshot: 0,
+        };
+        (repo, loaded)
+    }
+}
+
+impl NoteRepository for JsonRepository {
+    fn get(&self, id: &str) -> Result<Option<Note>, String> {
+        Ok(self.notes.get(id).cloned())
+    }
+
+    fn put(&mut self, note: &Note) -> Result<(), String> {
+        self.apply(&[Change::Put(Box::new(note.clone()))])
+    }
+
+    fn delete(&mut self, id: &str) -> Result<(), String> {
+        self.apply(&[Change::Delete(id.to_string())])
+    }
+
+    fn list(&self) -> Result<Vec<Note>, String> {
+        Ok(self.notes.values().cloned().collect())
+    }
+
+    fn apply(&mut self, changes: &[Change]) -> Result<(), String> {
+        if snapshot_read_only() {
+            return Err(snapshot_hold().unwrap_or_default());
+        }
+        // Changes go straight into the map. The first previous value of each
+        // touched note is kept, so a failed append can put the repository
+        // back as it was and the writer's retry produces the same records.
+        let mut undo: HashMap<String, Option<Note>> = HashMap::new();
+        let mut records = Vec::new();
+        for change in changes {
+            if let Some(record) = journal::record_for(&self.notes, change) {
+                let id = match change {
+                    Change::Put(note) => &note.id,
+                    Change::Delete(id) => id,
+                };
+                undo.entry(id.clone())
+                    .or_insert_with(|| self.notes.get(id).cloned());
+                journal::apply_record(&mut self.notes, record.clone());
+                records.push(record);
+            }
+        }
+        if let Err(e) = journal::append(&records) {
+            for (id, previous) in undo {
+                match previous {
+                    Some(note) => self.notes.insert(id, note),
+                    None => self.notes.remove(&id),
+                };
+            }
+            return Err(format!("Failed to save notes: {}", e));
+        }
+        self.records_since_snapshot += records.len();
+
+        // If notes.json was changed by someone else, leave it for the file
+        // watcher to merge rather than overwriting it with our copy. A held
+        // snapshot keeps everything in the journal.
+        if journal::needs_compaction(self.records_since_snapshot)
+            && !snapshot_changed()
+            && snapshot_hold().is_none()
+        {
+            save_notes(&self.notes)?;
+            self.records_since_snapshot = 0;
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<(), String> {
+        // Same rule as above: an outside change is merged first.
+        if snapshot_changed() {
+            return Err("notes.json was changed outside the app".to_string());
+        }
+        save_notes(&self.notes)?;
+        self.records_since_snapshot = 0;
+        Ok(())
+    }
+
+    fn replace_all(&mut self, notes: &[Note]) -> Result<(), String> {
+        self.notes = notes.iter().map(|n| (n.id.clone(), n.clone())).collect();
+        save_notes(&self.notes)?;
+        self.records_since_snapshot = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use super::crypto::{self, Key};
+use super::repository::{Change, NoteRepository};
+use crate::notes::Note;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Stores each note as its own row so edits only touch that row. Notes are
+/// kept as serialized JSON to share the serde defaults of the JSON store.
+pub struct SqliteRepository {
+    conn: Connection,
+}
+
+impl SqliteRepository {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             PRAGMA synchronous = NORMAL;
+             PRAGMA secure_delete = ON;
+             CREATE TABLE IF NOT EXISTS notes (
+                 id TEXT PRIMARY KEY,
+                 updated_at TEXT NOT NULL,
+                 data TEXT NOT NULL
+             );",
+        )
+        .map_err(|e| format!("Failed to open notes database: {}", e))?;
+        Ok(SqliteRepository { conn })
+    }
+}
+
+/// Re-encrypts every row after the key changed, then vacuums so no copy of
+/// the old rows is left behind in free pages or the WAL.
+pub fn reseal(path: &Path, old: Option<&Key>, new: Option<&Key>) -> Result<(), String> {
+    let mut repo = SqliteRepository::open(path)?;
+    let tx = repo.conn.transaction().map_err(|e| e.to_string())?;
+    let rows: Vec<(String, String)> = {
+        let mut stmt = tx
+            .prepare("SELECT id, data FROM notes")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+    };
+    for (id, data) in rows {
+        let plain = crypto::open_text_with(old, &data)
+            .or_else(|e| crypto::open_text_with(new, &data).map_err(|_| e))?;
+        let data = crypto::seal_text_with(new, &plain)?;
+        tx.execute(
+            "UPDATE notes SET data = ?1 WHERE id = ?2",
+            params![data, id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    repo.conn
+        .execute_batch("PRAGMA wal_checkpoint(TRUNCATE); VACUUM;")
+        .map_err(|e| format!("Failed to compact notes database: {}", e))
+}
+
+fn decode(data: String) -> Result<Note, String> {
+    let data = crypto::open_text(&data)?;
+    serde_json::from_str(&data).map_err(|e| e.to_string())
+}
+
+// Takes a plain connection so the same statements run inside a transaction.
+fn upsert(conn: &Connection, note: &Note) -> Result<(), String> {
+    let data = serde_json::to_string(note).map_err(|e| e.to_string())?;
+    let data = crypto::seal_text(&data)?;
+    conn.execute(
+        "INSERT INTO notes (id, updated_at, data) VALUES (?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET updated_at = excluded.updated_at, data = excluded.data",
+        params![note.id, note.updated_at, data],
+    )
+    .map_err(|e| format!("Failed to save note: {}", e))?;
+    Ok(())
+}
+
+fn remove(conn: &Connection, id: &str) -> Result<(), String> {
+    conn.execute("DELETE FROM notes WHERE id = ?1", params![id])
+        .map_err(|e| format!("Failed to delete note: {}", e))?;
+    Ok(())
+}
+
+impl NoteRepository for SqliteRepository {
+    fn get(&self, id: &str) -> Result<Option<Note>, String> {
+        let data: Option<String> = self
+            .conn
+            .query_row("SELECT data FROM notes WHERE id = ?1", params![id], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map_err(|e| e.to_string())?;
+        data.map(decode).transpose()
+    }
+
+    fn put(&mut self, note: &Note) -> Result<(), String> {
+        upsert(&self.conn, note)
+    }
+
+    fn delete(&mut self, id: &str) -> Result<(), String> {
+        remove(&self.conn, id)
+    }
+
+    fn list(&self) -> Result<Vec<Note>, String> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT data FROM notes")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(|e| e.to_string())?;
+        let mut notes = Vec::new();
+        for row in rows {
+            notes.push(decode(row.map_err(|e| e.to_string())?)?);
+        }
+        Ok(notes)
+    }
+
+    /// Rewrites the database file, so old rows are gone from the WAL and
+    /// from free pages.
+    fn compact(&mut self) -> Result<(), String> {
+        self.conn
+            .execute_batch(
+                "PRAGMA wal_checkpoint(TRUNCATE); VACUUM; PRAGMA wal_checkpoint(TRUNCATE);",
+            )
+            .map_err(|e| format!("Failed to compact notes database: {}", e))
+    }
+
+    /// Writes the whole batch in one transaction, so it lands all at once
+    /// and costs a single sync.
+    fn apply(&mut self, changes: &[Change]) -> Result<(), String> {
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        for change in changes {
+            match change {
+                Change::Put(note) => upsert(&tx, note)?,
+                Change::Delete(id) => remove(&tx, id)?,
+            }
+        }
+        tx.commit()
+            .map_err(|e| format!("Failed to save notes: {}", e))
+    }
+
+    fn replace_all(&mut self, notes: &[Note]) -> Result<(), String> {
+        let keep: HashSet<&str> = notes.iter().map(|n| n.id.as_str()).collect();
+        let tx = self.conn.transaction().map_err(|e| e.to_string())?;
+        let existing: Vec<String> = {
+            let mut stmt = tx
+                .prepare("SELECT id FROM notes")
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map([], |row| row.get(0))
+                .map_err(|e| e.to_string())?;
+            rows.collect::<Result<_, _>>().map_err(|e| e.to_string())?
+        };
+        for id in existing.iter().filter(|id| !keep.contains(id.as_str())) {
+            remove(&tx, id)?;
+        }
+        for note in notes {
+            upsert(&tx, note)?;
+        }
+        tx.commit()
+            .map_err(|e| format!("Failed to save notes: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(id: &str, content: &str) -> Note {
+        serde_json::from_value(serde_json::json!({ "id": id, "content": content })).unwrap()
+    }
+
+    #[test]
+    fn replace_all_keeps_exactly_the_given_notes() {
+        let path = std::env::temp_dir().join(format!("stickynotes-{}.db", uuid::Uuid::new_v4()));
+        let mut repo = SqliteRepository::open(&path).unwrap();
+        repo.apply(&[
+            Change::Put(Box::new(note("a", "old"))),
+            Change::Put(Box::new(note("b", "gone"))),
+        ])
+        .unwrap();
+
+        repo.replace_all(&[note("a", "new"), note("c", "added")])
+            .unwrap();
+
+        let mut ids: Vec<String> = repo.list().unwrap().into_iter().map(|n| n.id).collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "c"]);
+        assert_eq!(repo.get("a").unwrap().unwrap().content, "new");
+
+        drop(repo);
+        for suffix in ["", "-wal", "-shm"] {
+            std::fs::remove_file(format!("{}{}", path.display(), suffix)).ok();
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Independent note stores inside one data directory.
+//!
+//! The default workspace keeps using the top of the data directory, so stores
+//! from before workspaces existed need no migration. Every other workspace
+//! gets its own `workspaces/<name>/` with the same layout inside.
+
+use super::storage_dir;
+use crate::settings;
+use std::fs;
+use std::path::PathBuf;
+
+pub const DEFAULT_WORKSPACE: &str = "default";
+
+fn workspaces_root() -> PathBuf {
+    storage_dir().join("workspaces")
+}
+
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ' ' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid workspace name {:?}: use letters, digits, spaces, '-', '_' or '.'",
+            name
+        ))
+    }
+}
+
+pub fn active_workspace() -> String {
+    let name = settings::current().workspace;
+    if validate_name(&name).is_ok() {
+        name
+    } else {
+        DEFAULT_WORKSPACE.to_string()
+    }
+}
+
+fn dir_for(name: &str) -> PathBuf {
+    if name == DEFAULT_WORKSPACE {
+        storage_dir()
+    } else {
+        workspaces_root().join(name)
+    }
+}
+
+/// Directory holding the active workspace's notes and media.
+pub fn workspace_dir() -> PathBuf {
+    let dir = dir_for(&active_workspace());
+    fs::create_dir_all(&dir).ok();
+    dir
+}
+
+/// Directories of every workspace, the active one included.
+pub fn all_workspace_dirs() -> Vec<PathBuf> {
+    list_workspaces().iter().map(|name| dir_for(name)).collect()
+}
+
+pub fn list_workspaces() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(workspaces_root())
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_dir())
+                .filter_map(|e| e.file_name().to_str().map(String::from))
+                .filter(|name| validate_name(name).is_ok() && name != DEFAULT_WORKSPACE)
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort_by_key(|name| name.to_lowercase());
+    names.insert(0, DEFAULT_WORKSPACE.to_string());
+    names
+}
+
+pub fn create_workspace(name: &str) -> Result<(), String> {
+    validate_name(name)?;
+    fs::create_dir_all(dir_for(name))
+        .map_err(|e| format!("Failed to create workspace {}: {}", name, e))
+}
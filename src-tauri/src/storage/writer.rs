@@ -0,0 +1,236 @@
+//! Background persistence for `NotesStore`.
+//!
+//! Commands record changes here instead of writing to disk themselves. A
+//! dedicated thread waits until edits have been quiet for `DEBOUNCE` (or dirty
+//! for `MAX_DELAY`, so a long drag still lands on disk) and hands the whole
+//! batch to the repository in one go. Later changes to the same note replace
+//! earlier ones, so dragging a window only ever keeps its latest position.
+
+use super::repository::{Change, NoteRepository};
+use crate::notes::Note;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEBOUNCE: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(3);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Queue {
+    // Insertion order is kept so a create followed by a delete of another
+    // note reaches the repository in the order it happened.
+    order: Vec<String>,
+    changes: HashMap<String, Change>,
+    first_change: Option<Instant>,
+    last_change: Option<Instant>,
+    shutdown: bool,
+}
+
+impl Queue {
+    fn push(&mut self, id: String, change: Change) {
+        let now = Instant::now();
+        if self.changes.insert(id.clone(), change).is_none() {
+            self.order.push(id);
+        }
+        self.first_change.get_or_insert(now);
+        self.last_change = Some(now);
+    }
+
+    fn take(&mut self) -> Vec<Change> {
+        self.first_change = None;
+        self.last_change = None;
+        let mut changes = std::mem::take(&mut self.changes);
+        self.order
+            .drain(..)
+            .filter_map(|id| changes.remove(&id))
+            .collect()
+    }
+
+    fn due_at(&self) -> Option<Instant> {
+        let first = self.first_change?;
+        let last = self.last_change?;
+        Some((last + DEBOUNCE).min(first + MAX_DELAY))
+    }
+}
+
+pub struct NoteWriter {
+    queue: Mutex<Queue>,
+    wake: Condvar,
+    repo: Mutex<Option<Box<dyn NoteRepository>>>,
+    started: AtomicBool,
+}
+
+impl NoteWriter {
+    pub fn new() -> Arc<Self> {
+        Arc::new(NoteWriter {
+            queue: Mutex::new(Queue::default()),
+            wake: Condvar::new(),
+            repo: Mutex::new(None),
+            started: AtomicBool::new(false),
+        })
+    }
+
+    /// Starts the writer thread. Failed flushes are passed to `on_error` and
+    /// retried after `RETRY_DELAY`. Later calls do nothing.
+    pub fn start(self: &Arc<Self>, on_error: impl Fn(String) + Send + 'static) {
+        if self.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let writer = Arc::clone(self);
+        thread::Builder::new()
+            .name("note-writer".into())
+            .spawn(move || writer.run(on_error))
+            .expect("failed to spawn note writer thread");
+    }
+
+    fn run(&self, on_error: impl Fn(String)) {
+        loop {
+            {
+                let mut queue = match self.queue.lock() {
+                    Ok(queue) => queue,
+                    Err(_) => return,
+                };
+                loop {
+                    if queue.shutdown {
+                        return;
+                    }
+                    match queue.due_at() {
+                        None => queue = self.wake.wait(queue).unwrap_or_else(|e| e.into_inner()),
+                        Some(due) => {
+                            let now = Instant::now();
+                            if now >= due {
+                                break;
+                            }
+                            queue = self
+                                .wake
+                                .wait_timeout(queue, due - now)
+                                .map(|(q, _)| q)
+                                .unwrap_or_else(|e| e.into_inner().0);
+                        }
+                    }
+                }
+            }
+
+            if let Err(e) = self.flush() {
+                eprintln!("failed to save notes, will retry: {}", e);
+                on_error(e);
+                thread::sleep(RETRY_DELAY);
+            }
+        }
+    }
+
+    fn push(&self, id: String, change: Change) {
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.push(id, change);
+            self.wake.notify_one();
+        }
+    }
+
+    pub fn put(&self, note: &Note) {
+        self.push(note.id.clone(), Change::Put(Box::new(note.clone())));
+    }
+
+    pub fn delete(&self, id: &str) {
+        self.push(id.to_string(), Change::Delete(id.to_string()));
+    }
+
+    /// Writes everything queued so far and returns once it is on disk.
+    pub fn flush(&self) -> Result<(), String> {
+        // Holding the repository lock while taking the queue keeps batches
+        // from two concurrent flushes from landing out of order.
+        let mut repo = self.repo.lock().map_err(|e| e.to_string())?;
+        let changes = self.queue.lock().map_err(|e| e.to_string())?.take();
+        if changes.is_empty() {
+            return Ok(());
+        }
+        let Some(repo) = repo.as_mut() else {
+            self.requeue(changes);
+            return Err("Storage is not open".to_string());
+        };
+        if let Err(e) = repo.apply(&changes) {
+            self.requeue(changes);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Flushes, then has the repository drop whatever earlier writes left
+    /// behind on disk.
+    pub fn compact(&self) -> Result<(), String> {
+        self.flush()?;
+        let mut repo = self.repo.lock().map_err(|e| e.to_string())?;
+        match repo.as_mut() {
+            Some(repo) => repo.compact(),
+            None => Err("Storage is not open".to_string()),
+        }
+    }
+
+    /// Puts a failed batch back without overwriting anything newer.
+    fn requeue(&self, changes: Vec<Change>) {
+        if let Ok(mut queue) = self.queue.lock() {
+            let newer = std::mem::take(&mut queue.changes);
+            let newer_order = std::mem::take(&mut queue.order);
+            for change in changes {
+                let id = match &change {
+                    Change::Put(note) => note.id.clone(),
+                    Change::Delete(id) => id.clone(),
+                };
+                queue.push(id, change);
+            }
+            for id in newer_order {
+                if let Some(change) = newer.get(&id) {
+                    queue.push(id, change.clone());
+                }
+            }
+        }
+    }
+
+    /// Flushes pending changes and then swaps in a different repository.
+    /// If the flush fails the current repository stays and the error is
+    /// returned, since the changes belong to it and not to `repo`, which may
+    /// be another workspace or backend. Changes queued while no repository
+    /// was open have nowhere to go and are dropped.
+    pub fn set_repo(&self, repo: Box<dyn NoteRepository>) -> Result<(), String> {
+        let mut current = self.repo.lock().map_err(|e| e.to_string())?;
+        let changes = self.queue.lock().map_err(|e| e.to_string())?.take();
+        match current.as_mut() {
+            Some(current) if !changes.is_empty() => {
+                if let Err(e) = current.apply(&changes) {
+                    self.requeue(changes);
+                    return Err(e);
+                }
+            }
+            None if !changes.is_empty() => {
+                eprintln!(
+                    "dropping {} changes queued while storage was closed",
+                    changes.len()
+                );
+            }
+            _ => {}
+        }
+        *current = Some(repo);
+        Ok(())
+    }
+
+    /// Flushes pending changes and drops the repository, releasing any files
+    /// it holds open. Writes fail until `set_repo` is called again.
+    pub fn close(&self) -> Result<(), String> {
+        self.flush()?;
+        *self.repo.lock().map_err(|e| e.to_string())? = None;
+        Ok(())
+    }
+
+    /// Flushes and stops the writer thread. Called when the app exits.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.flush() {
+            eprintln!("failed to save notes on exit: {}", e);
+        }
+        if let Ok(mut queue) = self.queue.lock() {
+            queue.shutdown = true;
+            self.wake.notify_one();
+        }
+    }
+}
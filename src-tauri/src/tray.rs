@@ -1,23 +1,124 @@
 use crate::commands;
 use crate::notes::NotesState;
+use crate::storage::{self, backup, crypto};
 use tauri::{
-    menu::{Menu, MenuItem},
+    menu::{CheckMenuItem, IsMenuItem, Menu, MenuItem, PredefinedMenuItem, Submenu},
     tray::TrayIconBuilder,
-    AppHandle, Manager,
+    AppHandle, Manager, Wry,
 };
+use tauri_plugin_opener::OpenerExt;
 
-pub fn setup_tray(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+const TRAY_ID: &str = "main";
+const WORKSPACE_PREFIX: &str = "workspace:";
+const BACKUP_PREFIX: &str = "backup:";
+const BACKUP_MENU_LEN: usize = 10;
+
+fn backup_label(info: &backup::BackupInfo) -> String {
+    let when = chrono::DateTime::parse_from_rfc3339(&info.created_at)
+        .map(|at| {
+            at.with_timezone(&chrono::Local)
+                .format("%b %-d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|_| info.created_at.clone());
+    let kind = match info.kind {
+        backup::BackupKind::Daily => "Daily",
+        backup::BackupKind::Weekly => "Weekly",
+        backup::BackupKind::PreRestore => "Before restore",
+    };
+    format!("{} · {} ({} notes)", when, kind, info.notes)
+}
+
+fn build_menu(app: &AppHandle) -> tauri::Result<Menu<Wry>> {
     let new_note = MenuItem::with_id(app, "new_note", "New Note", true, None::<&str>)?;
     let show_all = MenuItem::with_id(app, "show_all", "Show All Notes", true, None::<&str>)?;
     let hide_all = MenuItem::with_id(app, "hide_all", "Hide All Notes", true, None::<&str>)?;
+    let export = MenuItem::with_id(
+        app,
+        "export_archive",
+        "Export Archive...",
+        true,
+        None::<&str>,
+    )?;
+    let import = MenuItem::with_id(
+        app,
+        "import_archive",
+        "Import Archive...",
+        true,
+        None::<&str>,
+    )?;
     let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
 
-    let menu = Menu::with_items(
+    let active = storage::active_workspace();
+    let workspace_items = storage::list_workspaces()
+        .into_iter()
+        .map(|name| {
+            let id = format!("{}{}", WORKSPACE_PREFIX, name);
+            let checked = name == active;
+            CheckMenuItem::with_id(app, id, name, true, checked, None::<&str>)
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let workspace_refs: Vec<&dyn IsMenuItem<Wry>> = workspace_items
+        .iter()
+        .map(|item| item as &dyn IsMenuItem<Wry>)
+        .collect();
+    let workspaces = Submenu::with_items(app, "Workspace", true, &workspace_refs)?;
+
+    // Manifests are sealed, so nothing can be listed before unlocking.
+    let backups = if crypto::is_locked() {
+        Vec::new()
+    } else {
+        backup::list()
+    };
+    let backup_items = backups
+        .iter()
+        .take(BACKUP_MENU_LEN)
+        .map(|info| {
+            let id = format!("{}{}", BACKUP_PREFIX, info.id);
+            MenuItem::with_id(app, id, backup_label(info), true, None::<&str>)
+        })
+        .collect::<tauri::Result<Vec<_>>>()?;
+    let backup_refs: Vec<&dyn IsMenuItem<Wry>> = backup_items
+        .iter()
+        .map(|item| item as &dyn IsMenuItem<Wry>)
+        .collect();
+    let restore = Submenu::with_items(app, "Restore Backup", !backups.is_empty(), &backup_refs)?;
+    let separator = PredefinedMenuItem::separator(app)?;
+
+    Menu::with_items(
         app,
-        &[&new_note, &show_all, &hide_all, &quit],
-    )?;
+        &[
+            &new_note,
+            &show_all,
+            &hide_all,
+            &separator,
+            &workspaces,
+            &restore,
+            &export,
+            &import,
+            &quit,
+        ],
+    )
+}
+
+/// Rebuilds the tray menu, e.g. after the active workspace changed.
+pub fn refresh_menu(app: &AppHandle) {
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        match build_menu(app) {
+            Ok(menu) => {
+                if let Err(e) = tray.set_menu(Some(menu)) {
+                    eprintln!("failed to update tray menu: {}", e);
+                }
+            }
+            Err(e) => eprintln!("failed to build tray menu: {}", e),
+        }
+    }
+}
 
-    TrayIconBuilder::new()
+pub fn setup_tray(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+    let menu = build_menu(app)?;
+
+    TrayIconBuilder::with_id(TRAY_ID)
         .icon(app.default_window_icon().unwrap().clone())
         .menu(&menu)
         .show_menu_on_left_click(true)
@@ -40,10 +141,56 @@ pub fn setup_tray(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
                     eprintln!("failed to hide all notes from tray: {}", e);
                 }
             }
+            // Both wait on native dialogs, which must not block the main
+            // thread.
+            "export_archive" => {
+                let app = app.clone();
+                std::thread::spawn(move || match commands::export_archive_with_dialog(&app) {
+                    Ok(Some(summary)) => {
+                        if let Err(e) = app.opener().reveal_item_in_dir(&summary.path) {
+                            eprintln!("failed to show exported archive: {}", e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!("failed to export archive from tray: {}", e),
+                });
+            }
+            "import_archive" => {
+                let app = app.clone();
+                std::thread::spawn(move || {
+                    match commands::import_archive_with_dialog(&app, None) {
+                        // A replace was backed up and belongs in the list.
+                        Ok(Some(_)) => refresh_menu(&app),
+                        Ok(None) => {}
+                        Err(e) => eprintln!("failed to import archive from tray: {}", e),
+                    }
+                });
+            }
             "quit" => {
                 app.exit(0);
             }
-            _ => {}
+            id => {
+                if let Some(name) = id.strip_prefix(WORKSPACE_PREFIX) {
+                    let state = app.state::<NotesState>();
+                    if let Err(e) =
+                        commands::switch_workspace(name.to_string(), app.clone(), state)
+                    {
+                        eprintln!("failed to switch workspace from tray: {}", e);
+                    }
+                    // A CheckMenuItem toggles itself on click; rebuild so only
+                    // the active workspace stays checked.
+                    refresh_menu(app);
+                } else if let Some(backup_id) = id.strip_prefix(BACKUP_PREFIX) {
+                    let state = app.state::<NotesState>();
+                    if let Err(e) =
+                        commands::restore_backup(backup_id.to_string(), app.clone(), state)
+                    {
+                        eprintln!("failed to restore backup from tray: {}", e);
+                    }
+                    // The restore itself was backed up and belongs in the list.
+                    refresh_menu(app);
+                }
+            }
         })
         .build(app)?;
 
@@ -0,0 +1,157 @@
+//! Watches notes.json for changes made by other programs (sync tools,
+//! scripts, another machine) and merges them into the running store.
+
+use crate::commands;
+use crate::notes::{Note, NotesState};
+use crate::settings::{self, StorageBackend};
+use crate::storage;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, PhysicalPosition, PhysicalSize};
+
+// Sync tools often write in several steps; wait for them to finish.
+const SETTLE: Duration = Duration::from_millis(300);
+
+static WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+
+fn touches_snapshot(event: &Event, snapshot: &Path) -> bool {
+    !matches!(event.kind, EventKind::Access(_)) && event.paths.iter().any(|p| p == snapshot)
+}
+
+/// (Re)starts watching the active workspace. Call again whenever the
+/// storage location or workspace changes.
+pub fn watch(app: &AppHandle) {
+    let mut current = match WATCHER.lock() {
+        Ok(current) => current,
+        Err(e) => {
+            eprintln!("failed to lock file watcher: {}", e);
+            return;
+        }
+    };
+    // Dropping the old watcher closes its channel, which ends its thread.
+    *current = None;
+
+    let snapshot = storage::storage_path();
+    let dir = match snapshot.parent() {
+        Some(dir) => dir.to_path_buf(),
+        None => return,
+    };
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = match notify::recommended_watcher(tx) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            eprintln!("failed to create file watcher: {}", e);
+            return;
+        }
+    };
+    // Watch the directory, not the file: atomic saves replace the file.
+    if let Err(e) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+        eprintln!("failed to watch {}: {}", dir.display(), e);
+        return;
+    }
+
+    let app = app.clone();
+    std::thread::spawn(move || {
+        while let Ok(event) = rx.recv() {
+            match event {
+                Ok(event) if touches_snapshot(&event, &snapshot) => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    eprintln!("file watcher error: {}", e);
+                    continue;
+                }
+            }
+            while rx.recv_timeout(SETTLE).is_ok() {}
+            reload(&app);
+        }
+    });
+    *current = Some(watcher);
+}
+
+fn same_note(a: &Note, b: &Note) -> bool {
+    serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+}
+
+/// Reloads notes.json if it changed outside the app. Our own pending edits
+/// are flushed to the journal first and merged into the new file note by
+/// note, keeping whichever side changed a note last.
+fn reload(app: &AppHandle) {
+    if settings::current().storage_backend != StorageBackend::Json || !storage::snapshot_changed() {
+        return;
+    }
+
+    let state = app.state::<NotesState>();
+    let mut store = match state.lock() {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("failed to lock notes for reload: {}", e);
+            return;
+        }
+    };
+    // The writer keeps its repository until the new one is ready, so edits
+    // are still saved if anything below fails.
+    // Reloading rewrites notes.json, which would lose whatever is not in it
+    // yet; try again on the next change instead.
+    if let Err(e) = store.writer.flush() {
+        eprintln!("failed to flush notes before reload: {}", e);
+        return;
+    }
+    let (repo, loaded) = storage::JsonRepository::reload();
+    if let Err(e) = store.writer.set_repo(Box::new(repo)) {
+        eprintln!("failed to reopen notes storage: {}", e);
+        return;
+    }
+
+    let previous = store.set_notes(loaded.notes);
+    let mut created = Vec::new();
+    let mut updated = Vec::new();
+    for (id, note) in &store.notes {
+        match previous.get(id) {
+            None => created.push(id.clone()),
+            Some(old) if !same_note(old, note) => updated.push((old.clone(), note.clone())),
+            Some(_) => {}
+        }
+    }
+    let deleted: Vec<String> = previous
+        .keys()
+        .filter(|id| !store.notes.contains_key(*id))
+        .cloned()
+        .collect();
+    for id in &deleted {
+        store.unlocked_notes.remove(id);
+    }
+    drop(store);
+
+    for id in &created {
+        commands::emit_external_change(app, "created", id);
+    }
+    for (old, note) in &updated {
+        if let Some(window) = app.get_webview_window(&note.id) {
+            if (old.x, old.y) != (note.x, note.y) {
+                let _ = window.set_position(PhysicalPosition::new(note.x as i32, note.y as i32));
+            }
+            if (old.width, old.height) != (note.width, note.height) {
+                let _ = window.set_size(PhysicalSize::new(note.width as u32, note.height as u32));
+            }
+        }
+        commands::emit_external_change(app, "updated", &note.id);
+    }
+    for id in &deleted {
+        if let Some(window) = app.get_webview_window(id) {
+            if let Err(e) = window.destroy() {
+                eprintln!("failed to close window for deleted note {}: {}", id, e);
+            }
+        }
+        commands::emit_external_change(app, "deleted", id);
+    }
+
+    if let Some(report) = loaded.recovery {
+        let _ = app.emit("storage-recovered", report);
+    }
+    if let Some(reason) = storage::snapshot_hold() {
+        let _ = app.emit("storage-error", reason);
+    }
+}
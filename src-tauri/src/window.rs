@@ -14,6 +14,45 @@ fn parse_hex_color(hex: &str) -> Color {
     }
 }
 
+/// Label of the window that asks for the passphrase when encryption is on.
+pub const UNLOCK_WINDOW: &str = "unlock";
+
+fn page_url(app: &AppHandle, query: &str) -> Result<WebviewUrl, String> {
+    let config = app.config();
+    let base = if cfg!(debug_assertions) {
+        config
+            .build
+            .dev_url
+            .as_ref()
+            .map(|u| u.to_string())
+            .unwrap_or_else(|| "http://localhost:1420".to_string())
+    } else {
+        "tauri://localhost".to_string()
+    };
+    let full = format!("{}/?{}", base.trim_end_matches('/'), query);
+    Ok(WebviewUrl::External(full.parse().map_err(|e: url::ParseError| e.to_string())?))
+}
+
+pub fn open_unlock_window(app: &AppHandle) -> Result<(), String> {
+    if let Some(window) = app.get_webview_window(UNLOCK_WINDOW) {
+        window.set_focus().map_err(|e: tauri::Error| e.to_string())?;
+        return Ok(());
+    }
+
+    let url = page_url(app, "unlock=1")?;
+    WebviewWindowBuilder::new(app, UNLOCK_WINDOW, url)
+        .title("Unlock Sticky Notes")
+        .inner_size(340.0, 180.0)
+        .center()
+        .decorations(false)
+        .resizable(false)
+        .always_on_top(true)
+        .background_color(Color(10, 10, 10, 255))
+        .build()
+        .map_err(|e: tauri::Error| e.to_string())?;
+    Ok(())
+}
+
 pub fn open_note_window(app: &AppHandle, note: &Note) -> Result<(), String> {
     // Check if window already exists
     if let Some(window) = app.get_webview_window(&note.id) {
@@ -24,21 +63,7 @@ pub fn open_note_window(app: &AppHandle, note: &Note) -> Result<(), String> {
         return Ok(());
     }
 
-    let url = {
-        let config = app.config();
-        let base = if cfg!(debug_assertions) {
-            config
-                .build
-                .dev_url
-                .as_ref()
-                .map(|u| u.to_string())
-                .unwrap_or_else(|| "http://localhost:1420".to_string())
-        } else {
-            "tauri://localhost".to_string()
-        };
-        let full = format!("{}/?noteId={}", base.trim_end_matches('/'), note.id);
-        WebviewUrl::External(full.parse().map_err(|e: url::ParseError| e.to_string())?)
-    };
+    let url = page_url(app, &format!("noteId={}", note.id))?;
 
     let window = WebviewWindowBuilder::new(app, &note.id, url)
         .title("Sticky Note")
@@ -78,21 +103,7 @@ pub fn open_note_window_centered(app: &AppHandle, note: &Note) -> Result<(), Str
         return Ok(());
     }
 
-    let url = {
-        let config = app.config();
-        let base = if cfg!(debug_assertions) {
-            config
-                .build
-                .dev_url
-                .as_ref()
-                .map(|u| u.to_string())
-                .unwrap_or_else(|| "http://localhost:1420".to_string())
-        } else {
-            "tauri://localhost".to_string()
-        };
-        let full = format!("{}/?noteId={}", base.trim_end_matches('/'), note.id);
-        WebviewUrl::External(full.parse().map_err(|e: url::ParseError| e.to_string())?)
-    };
+    let url = page_url(app, &format!("noteId={}", note.id))?;
 
     let window = WebviewWindowBuilder::new(app, &note.id, url)
         .title("Sticky Note")
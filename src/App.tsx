@@ -1,10 +1,15 @@
 import { NoteWindow } from "./components/NoteWindow";
+import { UnlockWindow } from "./components/UnlockWindow";
 import "./styles/global.css";
 
 function App() {
   const params = new URLSearchParams(window.location.search);
   const noteId = params.get("noteId");
 
+  if (params.get("unlock")) {
+    return <UnlockWindow />;
+  }
+
   if (!noteId) {
     return null;
   }
@@ -3,6 +3,12 @@ import { useEffect, useRef, useState } from "react";
 interface Props {
   x: number;
   y: number;
+  locked: boolean;
+  unlocked: boolean;
+  onLock: () => void;
+  onRelock: () => void;
+  onRemoveLock: () => void;
+  onHistory: () => void;
   onDelete: () => void;
   onClose: () => void;
   closing?: boolean;
@@ -11,6 +17,12 @@ interface Props {
 export function ContextMenu({
   x,
   y,
+  locked,
+  unlocked,
+  onLock,
+  onRelock,
+  onRemoveLock,
+  onHistory,
   onDelete,
   onClose,
   closing,
@@ -57,6 +69,55 @@ export function ContextMenu({
       role="menu"
       aria-label="Note options"
     >
+      {!locked && (
+        <button
+          className="context-menu-item"
+          onClick={() => {
+            onClose();
+            onLock();
+          }}
+          type="button"
+        >
+          Lock Note...
+        </button>
+      )}
+      {locked && unlocked && (
+        <>
+          <button
+            className="context-menu-item"
+            onClick={() => {
+              onClose();
+              onRelock();
+            }}
+            type="button"
+          >
+            Lock Now
+          </button>
+          <button
+            className="context-menu-item"
+            onClick={() => {
+              onClose();
+              onRemoveLock();
+            }}
+            type="button"
+          >
+            Remove Lock...
+          </button>
+        </>
+      )}
+      {!locked && (
+        <button
+          className="context-menu-item"
+          onClick={() => {
+            onClose();
+            onHistory();
+          }}
+          type="button"
+        >
+          History...
+        </button>
+      )}
+      <div className="context-menu-divider" />
       <button
         className="context-menu-item delete"
         onClick={() => {
@@ -3,7 +3,10 @@ import StarterKit from "@tiptap/starter-kit";
 import Underline from "@tiptap/extension-underline";
 import Image from "@tiptap/extension-image";
 import { useEffect, useRef, useState, useCallback } from "react";
-import { saveImage } from "../lib/commands";
+import { getCurrentWebview } from "@tauri-apps/api/webview";
+import { importMedia, importMediaBytes, openAttachment } from "../lib/commands";
+import { AttachmentNode, attachmentFile } from "../lib/attachment";
+import { ImportedMedia } from "../types";
 
 interface Props {
   content: string;
@@ -12,16 +15,25 @@ interface Props {
   onDelete: () => void;
 }
 
-async function fileToMediaUrl(file: File): Promise<string | null> {
-  const validTypes = ["image/png", "image/jpeg", "image/jpg", "image/gif", "image/webp", "image/svg+xml"];
-  if (!validTypes.includes(file.type)) return null;
-
-  const buffer = await file.arrayBuffer();
-  const base64 = btoa(
-    new Uint8Array(buffer).reduce((s, b) => s + String.fromCharCode(b), "")
-  );
-  const filename = await saveImage(base64, file.type);
-  return `http://media.localhost/${filename}`;
+function insertMedia(editor: ReturnType<typeof useEditor>, media: ImportedMedia) {
+  if (!editor) return;
+  if (media.type === "image") {
+    // Notes show the downscaled copy; the original stays on disk.
+    editor.chain().focus().setImage({ src: `http://media.localhost/${media.file}?size=display` }).run();
+    return;
+  }
+  editor
+    .chain()
+    .focus()
+    .insertContent({
+      type: "attachment",
+      attrs: {
+        src: `http://media.localhost/${media.file}`,
+        name: media.name,
+        size: media.size,
+      },
+    })
+    .run();
 }
 
 export function NoteEditor({ content, fgColor, onChange, onDelete }: Props) {
@@ -30,42 +42,60 @@ export function NoteEditor({ content, fgColor, onChange, onDelete }: Props) {
 
   const initialContent = useRef(content);
 
+  const addFile = useCallback((file: File) => {
+    importMediaBytes(file)
+      .then((media) => insertMedia(editorRef.current, media))
+      .catch((e) => console.error("File was rejected:", e));
+  }, []);
+
   const handlePaste = useCallback((_view: unknown, event: ClipboardEvent) => {
     const items = event.clipboardData?.items;
     if (!items) return false;
 
     for (const item of items) {
-      if (item.type.startsWith("image/")) {
-        event.preventDefault();
-        const file = item.getAsFile();
-        if (!file) return true;
-        fileToMediaUrl(file).then((url) => {
-          if (url && editorRef.current) {
-            editorRef.current.chain().focus().setImage({ src: url }).run();
-          }
-        });
-        return true;
-      }
+      if (item.kind !== "file") continue;
+      const file = item.getAsFile();
+      if (!file) continue;
+      event.preventDefault();
+      addFile(file);
+      return true;
     }
     return false;
-  }, []);
+  }, [addFile]);
 
+  // Dropped files are inserted by the native drop listener below; only keep
+  // the editor from handling them a second time.
   const handleDrop = useCallback((_view: unknown, event: DragEvent) => {
-    const files = event.dataTransfer?.files;
-    if (!files?.length) return false;
-
-    for (const file of files) {
-      if (file.type.startsWith("image/")) {
-        event.preventDefault();
-        fileToMediaUrl(file).then((url) => {
-          if (url && editorRef.current) {
-            editorRef.current.chain().focus().setImage({ src: url }).run();
-          }
-        });
-        return true;
-      }
-    }
-    return false;
+    if (!event.dataTransfer?.files?.length) return false;
+    event.preventDefault();
+    return true;
+  }, []);
+
+  // Files dropped from the desktop arrive as paths through the native
+  // drag-and-drop event; Rust copies them without JS reading the file.
+  useEffect(() => {
+    let unlisten: (() => void) | undefined;
+    getCurrentWebview()
+      .onDragDropEvent((event) => {
+        if (event.payload.type !== "drop") return;
+        for (const path of event.payload.paths) {
+          importMedia(path)
+            .then((media) => insertMedia(editorRef.current, media))
+            .catch((e) => console.error("File was rejected:", e));
+        }
+      })
+      .then((fn) => { unlisten = fn; })
+      .catch((e) => console.error("Failed to listen for dropped files:", e));
+    return () => unlisten?.();
+  }, []);
+
+  const handleClick = useCallback((_view: unknown, _pos: number, event: MouseEvent) => {
+    const chip = (event.target as HTMLElement | null)?.closest("a[data-attachment]");
+    const file = chip && attachmentFile(chip.getAttribute("href") ?? "");
+    if (!file) return false;
+    event.preventDefault();
+    openAttachment(file).catch((e) => console.error("Failed to open attachment:", e));
+    return true;
   }, []);
 
   const editorRef = useRef<ReturnType<typeof useEditor>>(null);
@@ -80,6 +110,7 @@ export function NoteEditor({ content, fgColor, onChange, onDelete }: Props) {
         inline: false,
         allowBase64: false,
       }),
+      AttachmentNode,
 
     ],
     content: initialContent.current,
@@ -93,6 +124,7 @@ export function NoteEditor({ content, fgColor, onChange, onDelete }: Props) {
       },
       handlePaste,
       handleDrop,
+      handleClick,
     },
   });
 
@@ -0,0 +1,110 @@
+import { useState, useEffect, useCallback } from "react";
+import { NoteDiff, VersionInfo } from "../types";
+import { listNoteVersions, diffNoteVersions, restoreNoteVersion } from "../lib/commands";
+
+interface Props {
+  noteId: string;
+  onRestored: () => void | Promise<void>;
+  onClose: () => void;
+}
+
+function formatTime(iso: string): string {
+  return new Date(iso).toLocaleString(undefined, {
+    month: "short",
+    day: "numeric",
+    hour: "2-digit",
+    minute: "2-digit",
+  });
+}
+
+export function NoteHistoryPane({ noteId, onRestored, onClose }: Props) {
+  const [versions, setVersions] = useState<VersionInfo[] | null>(null);
+  const [selected, setSelected] = useState<string | null>(null);
+  const [diff, setDiff] = useState<NoteDiff | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  useEffect(() => {
+    listNoteVersions(noteId)
+      .then(setVersions)
+      .catch((e) => setError(String(e)));
+  }, [noteId]);
+
+  useEffect(() => {
+    const onKeyDown = (e: KeyboardEvent) => {
+      if (e.key === "Escape") onClose();
+    };
+    document.addEventListener("keydown", onKeyDown);
+    return () => document.removeEventListener("keydown", onKeyDown);
+  }, [onClose]);
+
+  const select = useCallback(
+    (versionId: string) => {
+      setSelected(versionId);
+      setDiff(null);
+      // What changed since the selected version; restoring brings back the
+      // deleted lines and drops the inserted ones.
+      diffNoteVersions(noteId, versionId)
+        .then(setDiff)
+        .catch((e) => setError(String(e)));
+    },
+    [noteId]
+  );
+
+  const restore = useCallback(async () => {
+    if (!selected) return;
+    try {
+      await restoreNoteVersion(noteId, selected);
+      await onRestored();
+    } catch (e) {
+      setError(String(e));
+    }
+  }, [noteId, selected, onRestored]);
+
+  return (
+    <div className="note-editor note-history-pane">
+      <div className="note-history-header">
+        <span>history</span>
+        <button className="note-history-action" onClick={onClose} type="button">
+          close
+        </button>
+      </div>
+      <div className="note-history-versions">
+        {versions?.map((version) => (
+          <button
+            key={version.id}
+            className={`note-history-version${selected === version.id ? " active" : ""}`}
+            onClick={() => select(version.id)}
+            type="button"
+          >
+            {formatTime(version.created_at)}
+          </button>
+        ))}
+        {versions?.length === 0 && <div className="notes-list-empty">&gt; no earlier versions</div>}
+      </div>
+      {selected && (
+        <>
+          <div className="note-history-preview">
+            {diff && diff.hunks.length === 0 && "same as the current note"}
+            {diff?.hunks.map((hunk) => (
+              <div key={`${hunk.old_start}:${hunk.new_start}`} className="note-history-hunk">
+                {hunk.lines.map((line, i) => (
+                  <div key={i} className={`note-history-line ${line.kind}`}>
+                    {line.segments.map((segment, j) => (
+                      <span key={j} className={segment.changed ? "changed" : undefined}>
+                        {segment.text}
+                      </span>
+                    ))}
+                  </div>
+                ))}
+              </div>
+            ))}
+          </div>
+          <button className="note-history-action" onClick={restore} type="button">
+            restore this version
+          </button>
+        </>
+      )}
+      {error && <div className="note-lock-status">{error}</div>}
+    </div>
+  );
+}
@@ -0,0 +1,66 @@
+import { useState, useCallback, useEffect, FormEvent } from "react";
+
+export type LockPaneMode = "unlock" | "lock" | "remove";
+
+interface Props {
+  mode: LockPaneMode;
+  onSubmit: (password: string) => Promise<void>;
+  onCancel?: () => void;
+}
+
+const LABELS: Record<LockPaneMode, string> = {
+  unlock: "note is locked",
+  lock: "set a password for this note",
+  remove: "password to remove lock",
+};
+
+export function NoteLockPane({ mode, onSubmit, onCancel }: Props) {
+  const [password, setPassword] = useState("");
+  const [error, setError] = useState<string | null>(null);
+  const [busy, setBusy] = useState(false);
+
+  useEffect(() => {
+    if (!onCancel) return;
+    const onKeyDown = (e: KeyboardEvent) => {
+      if (e.key === "Escape") onCancel();
+    };
+    document.addEventListener("keydown", onKeyDown);
+    return () => document.removeEventListener("keydown", onKeyDown);
+  }, [onCancel]);
+
+  const handleSubmit = useCallback(
+    async (e: FormEvent) => {
+      e.preventDefault();
+      if (!password || busy) return;
+      setBusy(true);
+      setError(null);
+      try {
+        await onSubmit(password);
+      } catch (err) {
+        setError(String(err));
+        setPassword("");
+      } finally {
+        setBusy(false);
+      }
+    },
+    [password, busy, onSubmit],
+  );
+
+  return (
+    <form className="note-editor note-lock-pane" onSubmit={handleSubmit}>
+      <label className="note-lock-label" htmlFor="note-lock-password">
+        {LABELS[mode]}
+      </label>
+      <input
+        id="note-lock-password"
+        className="note-lock-input"
+        type="password"
+        autoFocus
+        value={password}
+        disabled={busy}
+        onChange={(e) => setPassword(e.target.value)}
+      />
+      <div className="note-lock-status">{busy ? "..." : error}</div>
+    </form>
+  );
+}
@@ -6,7 +6,17 @@ import { NoteToolbar } from "./NoteToolbar";
 import { NoteEditor } from "./NoteEditor";
 import { NotesList } from "./NotesList";
 import { ContextMenu } from "./ContextMenu";
-import { deleteNote, listNotes, createNote } from "../lib/commands";
+import { NoteLockPane } from "./NoteLockPane";
+import { NoteHistoryPane } from "./NoteHistoryPane";
+import {
+  deleteNote,
+  listNotes,
+  createNote,
+  lockNote,
+  unlockNote,
+  relockNote,
+  removeNoteLock,
+} from "../lib/commands";
 import { withAlpha, isLightColor, deriveOverlayBg } from "../lib/colors";
 import { getDefaultTheme } from "../lib/preferences";
 import { RUST_DEFAULT_BG, RUST_DEFAULT_FG } from "../lib/constants";
@@ -24,7 +34,7 @@ export function NoteWindow({ noteId: initialNoteId }: Props) {
   const [listState, setListState] = useState<PanelState>('hidden');
   const listTimer = useRef<ReturnType<typeof setTimeout> | null>(null);
 
-  const { note, loading, updateContent, updateColors } =
+  const { note, loading, revision, updateContent, updateColors, flushSave, reload } =
     useNote(currentNoteId);
   useWindowControls(currentNoteId);
 
@@ -114,6 +124,53 @@ export function NoteWindow({ noteId: initialNoteId }: Props) {
     return () => document.removeEventListener("keydown", onKeyDown);
   }, [confirmDelete, currentNoteId, handleDeleteNote]);
 
+  // Password prompt for locking a note or removing its lock; unlocking is
+  // prompted for whenever a locked note is shown.
+  const [lockPrompt, setLockPrompt] = useState<"lock" | "remove" | null>(null);
+  const noteHidden = !!note?.lock && !note.lock.unlocked;
+
+  const [showHistory, setShowHistory] = useState(false);
+
+  useEffect(() => {
+    setLockPrompt(null);
+    setShowHistory(false);
+  }, [currentNoteId]);
+
+  const openHistory = useCallback(async () => {
+    // The history should include what was typed just now
+    await flushSave();
+    setShowHistory(true);
+  }, [flushSave]);
+
+  const handleVersionRestored = useCallback(async () => {
+    setShowHistory(false);
+    await reload();
+  }, [reload]);
+
+  const handleLockSubmit = useCallback(
+    async (password: string) => {
+      const id = currentNoteIdRef.current;
+      if (lockPrompt === "lock") {
+        // Pending edits must land before the content is sealed
+        await flushSave();
+        await lockNote(id, password);
+      } else if (lockPrompt === "remove") {
+        await removeNoteLock(id, password);
+      } else {
+        await unlockNote(id, password);
+      }
+      setLockPrompt(null);
+      await reload();
+    },
+    [lockPrompt, flushSave, reload]
+  );
+
+  const handleRelock = useCallback(async () => {
+    await flushSave();
+    await relockNote(currentNoteIdRef.current);
+    await reload();
+  }, [flushSave, reload]);
+
   const toggleList = useCallback(() => {
     if (listState === 'visible') {
       setListState('closing');
@@ -271,9 +328,22 @@ export function NoteWindow({ noteId: initialNoteId }: Props) {
               <span>{loading ? "..." : "_ not found"}</span>
             </div>
           </div>
+        ) : noteHidden || lockPrompt ? (
+          <NoteLockPane
+            key={`${currentNoteId}:${lockPrompt ?? "unlock"}`}
+            mode={lockPrompt ?? "unlock"}
+            onSubmit={handleLockSubmit}
+            onCancel={lockPrompt ? () => setLockPrompt(null) : undefined}
+          />
+        ) : showHistory ? (
+          <NoteHistoryPane
+            noteId={currentNoteId}
+            onRestored={handleVersionRestored}
+            onClose={() => setShowHistory(false)}
+          />
         ) : (
           <NoteEditor
-            key={currentNoteId}
+            key={`${currentNoteId}:${revision}`}
             content={note.content}
             fgColor={note.fg_color}
             onChange={updateContent}
@@ -286,6 +356,12 @@ export function NoteWindow({ noteId: initialNoteId }: Props) {
         <ContextMenu
           x={contextMenu.x}
           y={contextMenu.y}
+          locked={!!note?.lock}
+          unlocked={!!note?.lock?.unlocked}
+          onLock={() => setLockPrompt("lock")}
+          onRelock={() => handleRelock().catch(console.error)}
+          onRemoveLock={() => setLockPrompt("remove")}
+          onHistory={() => openHistory().catch(console.error)}
           onDelete={requestDelete}
           onClose={closeContextMenu}
           closing={contextMenu.closing}
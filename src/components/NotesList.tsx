@@ -1,7 +1,7 @@
 import { useState, useEffect, useCallback, useRef } from "react";
 import { listen, type UnlistenFn } from "@tauri-apps/api/event";
 import { Note } from "../types";
-import { listNotes, createNote } from "../lib/commands";
+import { listNotes, createNote, listTrash, restoreNote, emptyTrash } from "../lib/commands";
 
 interface Props {
   currentNoteId: string;
@@ -23,9 +23,18 @@ function preview(note: Note): string {
   return text.length > 50 ? text.slice(0, 50) + "..." : text;
 }
 
+const MEDIA_SRC = /http:\/\/media\.localhost\/[^"'?#\s<>)]+/;
+
+/** Thumbnail URL of the first image in the note, if it has one. */
+function thumbnail(note: Note): string | null {
+  const match = note.content.match(MEDIA_SRC);
+  return match ? `${match[0]}?size=thumb` : null;
+}
+
 export function NotesList({ currentNoteId, fgColor, onOpenNote, onDeleteNote, closing }: Props) {
   const [notes, setNotes] = useState<Note[]>([]);
   const [confirmId, setConfirmId] = useState<string | null>(null);
+  const [trash, setTrash] = useState<Note[] | null>(null);
   const refreshTimer = useRef<ReturnType<typeof setTimeout> | null>(null);
 
   const refresh = useCallback(() => {
@@ -40,6 +49,12 @@ export function NotesList({ currentNoteId, fgColor, onOpenNote, onDeleteNote, cl
       .catch((e) => console.error("Failed to list notes:", e));
   }, []);
 
+  const refreshTrash = useCallback(() => {
+    listTrash()
+      .then(setTrash)
+      .catch((e) => console.error("Failed to list trash:", e));
+  }, []);
+
   const queueRefresh = useCallback(() => {
     if (refreshTimer.current) {
       return;
@@ -141,6 +156,75 @@ export function NotesList({ currentNoteId, fgColor, onOpenNote, onDeleteNote, cl
     setConfirmId(id);
   };
 
+  const handleRestore = async (id: string) => {
+    await restoreNote(id);
+    refreshTrash();
+    refresh();
+    await onOpenNote(id);
+  };
+
+  const handleEmptyTrash = async () => {
+    await emptyTrash();
+    refreshTrash();
+  };
+
+  if (trash) {
+    return (
+      <div className={`notes-list${closing ? ' closing' : ''}`}>
+        <div className="notes-list-header">
+          <span>Trash ({trash.length})</span>
+          <button
+            className="notes-list-add"
+            onClick={() => setTrash(null)}
+            title="Back to notes"
+            style={{ color: fgColor }}
+            aria-label="Back to notes"
+            type="button"
+          >
+            ‹
+          </button>
+        </div>
+        <div className="notes-list-items">
+          {trash.map((note) => (
+            <div
+              key={note.id}
+              className="notes-list-item"
+              onClick={() => handleRestore(note.id).catch(console.error)}
+              onKeyDown={(e) => {
+                if (e.key === "Enter" || e.key === " ") {
+                  e.preventDefault();
+                  handleRestore(note.id).catch(console.error);
+                }
+              }}
+              role="button"
+              tabIndex={0}
+              title="Restore note"
+            >
+              <div className="notes-list-item-preview">{preview(note)}</div>
+              <div className="notes-list-item-meta">
+                deleted{" "}
+                {new Date(note.deleted_at ?? note.updated_at).toLocaleDateString(undefined, {
+                  month: "short",
+                  day: "numeric",
+                })}
+              </div>
+            </div>
+          ))}
+          {trash.length === 0 && <div className="notes-list-empty">&gt; trash is empty</div>}
+        </div>
+        {trash.length > 0 && (
+          <button
+            className="notes-list-hint notes-list-trash-empty"
+            onClick={() => handleEmptyTrash().catch(console.error)}
+            type="button"
+          >
+            empty trash
+          </button>
+        )}
+      </div>
+    );
+  }
+
   return (
     <div className={`notes-list${closing ? ' closing' : ''}`}>
       <div className="notes-list-header">
@@ -179,6 +263,9 @@ export function NotesList({ currentNoteId, fgColor, onOpenNote, onDeleteNote, cl
               </div>
             ) : (
               <>
+                {thumbnail(note) && (
+                  <img className="notes-list-item-thumb" src={thumbnail(note)!} alt="" loading="lazy" />
+                )}
                 <div className="notes-list-item-preview">{preview(note)}</div>
                 <div className="notes-list-item-meta">
                   {new Date(note.updated_at).toLocaleDateString(undefined, {
@@ -206,6 +293,9 @@ export function NotesList({ currentNoteId, fgColor, onOpenNote, onDeleteNote, cl
           ↑↓ navigate
         </div>
       )}
+      <button className="notes-list-hint notes-list-trash" onClick={refreshTrash} type="button">
+        trash
+      </button>
     </div>
   );
 }
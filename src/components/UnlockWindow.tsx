@@ -0,0 +1,45 @@
+import { useState, useCallback, FormEvent } from "react";
+import { unlockStore } from "../lib/commands";
+
+export function UnlockWindow() {
+  const [passphrase, setPassphrase] = useState("");
+  const [error, setError] = useState<string | null>(null);
+  const [busy, setBusy] = useState(false);
+
+  const handleSubmit = useCallback(
+    async (e: FormEvent) => {
+      e.preventDefault();
+      if (!passphrase || busy) return;
+      setBusy(true);
+      setError(null);
+      try {
+        // On success the backend closes this window and opens the notes.
+        await unlockStore(passphrase);
+      } catch (err) {
+        setError(String(err));
+        setPassphrase("");
+        setBusy(false);
+      }
+    },
+    [passphrase, busy],
+  );
+
+  return (
+    <form className="unlock-window" onSubmit={handleSubmit} data-tauri-drag-region>
+      <label className="unlock-label" htmlFor="unlock-passphrase" data-tauri-drag-region>
+        notes are encrypted
+      </label>
+      <input
+        id="unlock-passphrase"
+        className="unlock-input"
+        type="password"
+        autoFocus
+        placeholder="passphrase"
+        value={passphrase}
+        disabled={busy}
+        onChange={(e) => setPassphrase(e.target.value)}
+      />
+      <div className="unlock-status">{busy ? "unlocking..." : error}</div>
+    </form>
+  );
+}
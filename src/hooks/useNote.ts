@@ -1,10 +1,13 @@
 import { useState, useEffect, useRef, useCallback } from "react";
-import { Note } from "../types";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+import { Note, NotesChangedEvent } from "../types";
 import { getNote, updateNote } from "../lib/commands";
 
 export function useNote(noteId: string | null) {
   const [note, setNote] = useState<Note | null>(null);
   const [loading, setLoading] = useState(true);
+  // Bumped when the note is replaced from outside, so the editor remounts
+  const [revision, setRevision] = useState(0);
   const saveTimer = useRef<ReturnType<typeof setTimeout> | null>(null);
   const noteRef = useRef<Note | null>(null);
   const noteIdRef = useRef(noteId);
@@ -14,17 +17,19 @@ export function useNote(noteId: string | null) {
   noteRef.current = note;
   noteIdRef.current = noteId;
 
-  const flushSave = useCallback(() => {
+  const flushSave = useCallback((): Promise<void> => {
     if (saveTimer.current) {
       clearTimeout(saveTimer.current);
       saveTimer.current = null;
     }
     if (pendingRef.current) {
-      updateNote(pendingRef.current).catch((e) =>
+      const pending = updateNote(pendingRef.current).catch((e) =>
         console.error("Failed to save note:", e)
       );
       pendingRef.current = null;
+      return pending;
     }
+    return Promise.resolve();
   }, []);
 
   useEffect(() => {
@@ -59,6 +64,38 @@ export function useNote(noteId: string | null) {
     };
   }, [noteId, flushSave]);
 
+  // Pick up changes another program made to notes.json. Local edits that
+  // have not been saved yet win; they are written on top of the new file.
+  useEffect(() => {
+    if (!noteId) return;
+    let unlisten: UnlistenFn | null = null;
+    let cancelled = false;
+    listen<NotesChangedEvent>("notes-changed", (event) => {
+      const { kind, id, external } = event.payload;
+      if (!external || kind !== "updated" || id !== noteIdRef.current) return;
+      if (pendingRef.current) return;
+      getNote(id)
+        .then((n) => {
+          if (noteIdRef.current !== id) return;
+          setNote(n);
+          setRevision((r) => r + 1);
+        })
+        .catch((e) => console.error("Failed to reload note:", e));
+    })
+      .then((fn) => {
+        if (cancelled) {
+          fn();
+        } else {
+          unlisten = fn;
+        }
+      })
+      .catch((e) => console.error("Failed to subscribe to note changes:", e));
+    return () => {
+      cancelled = true;
+      unlisten?.();
+    };
+  }, [noteId]);
+
   // Flush on unmount
   useEffect(() => {
     return () => {
@@ -112,5 +149,22 @@ export function useNote(noteId: string | null) {
     [saveNote]
   );
 
-  return { note, loading, updateContent, updateColors, setNote: saveNote };
+  // Re-read the note without saving, e.g. after it was locked or unlocked
+  const reload = useCallback(async () => {
+    const id = noteIdRef.current;
+    if (!id) return;
+    const n = await getNote(id);
+    if (noteIdRef.current === id) setNote(n);
+  }, []);
+
+  return {
+    note,
+    loading,
+    revision,
+    updateContent,
+    updateColors,
+    setNote: saveNote,
+    flushSave,
+    reload,
+  };
 }
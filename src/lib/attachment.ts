@@ -0,0 +1,59 @@
+import { Node, mergeAttributes } from "@tiptap/react";
+
+const MEDIA_PREFIX = "http://media.localhost/";
+
+export function formatSize(bytes: number): string {
+  if (bytes < 1024) return `${bytes} B`;
+  if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(0)} KB`;
+  return `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+}
+
+/** Stored file name from an attachment chip's href. */
+export function attachmentFile(href: string): string | null {
+  return href.startsWith(MEDIA_PREFIX) ? href.slice(MEDIA_PREFIX.length) : null;
+}
+
+/**
+ * A non-image file shown inline as a chip. Stored in note HTML as
+ * `<a data-attachment href="http://media.localhost/<file>">`, so media GC
+ * sees the reference like it does for images.
+ */
+export const AttachmentNode = Node.create({
+  name: "attachment",
+  group: "inline",
+  inline: true,
+  atom: true,
+  selectable: true,
+
+  addAttributes() {
+    return {
+      src: {
+        default: null,
+        parseHTML: (el) => el.getAttribute("href"),
+        renderHTML: (attrs) => ({ href: attrs.src }),
+      },
+      name: {
+        default: "attachment",
+        parseHTML: (el) => el.getAttribute("data-name") ?? el.textContent,
+        renderHTML: (attrs) => ({ "data-name": attrs.name }),
+      },
+      size: {
+        default: 0,
+        parseHTML: (el) => Number(el.getAttribute("data-size")) || 0,
+        renderHTML: (attrs) => ({ "data-size": attrs.size }),
+      },
+    };
+  },
+
+  parseHTML() {
+    return [{ tag: "a[data-attachment]" }];
+  },
+
+  renderHTML({ node, HTMLAttributes }) {
+    return [
+      "a",
+      mergeAttributes(HTMLAttributes, { "data-attachment": "", class: "attachment-chip" }),
+      `${node.attrs.name} · ${formatSize(node.attrs.size)}`,
+    ];
+  },
+});
@@ -6,6 +6,7 @@ import { Note } from "../types";
 import { RUST_DEFAULT_BG, RUST_DEFAULT_FG, DEFAULT_WIDTH, DEFAULT_HEIGHT } from "./constants";
 
 const STORAGE_KEY = "stickynotes:notes";
+const TRASH_KEY = "stickynotes:trash";
 
 function loadNotes(): Note[] {
   try {
@@ -20,6 +21,19 @@ function saveNotes(notes: Note[]) {
   localStorage.setItem(STORAGE_KEY, JSON.stringify(notes));
 }
 
+function loadTrash(): Note[] {
+  try {
+    const raw = localStorage.getItem(TRASH_KEY);
+    return raw ? JSON.parse(raw) : [];
+  } catch {
+    return [];
+  }
+}
+
+function saveTrash(notes: Note[]) {
+  localStorage.setItem(TRASH_KEY, JSON.stringify(notes));
+}
+
 function makeNote(): Note {
   const now = new Date().toISOString();
   return {
@@ -59,8 +73,33 @@ const handlers: Record<string, (args?: any) => any> = {
     saveNotes(notes);
   },
   delete_note: (args: { id: string }) => {
-    const notes = loadNotes().filter((n) => n.id !== args.id);
-    saveNotes(notes);
+    const notes = loadNotes();
+    const note = notes.find((n) => n.id === args.id);
+    saveNotes(notes.filter((n) => n.id !== args.id));
+    if (note) saveTrash([{ ...note, deleted_at: new Date().toISOString() }, ...loadTrash()]);
+  },
+  list_trash: () => loadTrash(),
+  restore_note: (args: { id: string }) => {
+    const trash = loadTrash();
+    const note = trash.find((n) => n.id === args.id);
+    if (!note) throw new Error("Note not found in trash");
+    saveTrash(trash.filter((n) => n.id !== args.id));
+    delete note.deleted_at;
+    saveNotes([...loadNotes(), note]);
+    return note;
+  },
+  empty_trash: () => {
+    const count = loadTrash().length;
+    saveTrash([]);
+    return count;
+  },
+  list_note_versions: () => [],
+  get_note_version: (args: { versionId: string }) => {
+    throw new Error(`Version ${args.versionId} not found`);
+  },
+  diff_note_versions: () => ({ hunks: [], insertions: 0, deletions: 0 }),
+  restore_note_version: () => {
+    throw new Error("Note history is not available in the browser preview");
   },
   update_note_position: (args: { id: string; x: number; y: number; width: number; height: number }) => {
     const notes = loadNotes();
@@ -81,6 +120,85 @@ const handlers: Record<string, (args?: any) => any> = {
   save_image: (_args: { data: string; mimeType: string }) => {
     return `data:image/png;base64,placeholder`;
   },
+  save_attachment: (args: { data: string; fileName: string }) => ({
+    file: `${crypto.randomUUID()}.bin`,
+    name: args.fileName,
+    size: Math.floor((args.data.length * 3) / 4),
+    mime: "application/octet-stream",
+  }),
+  get_attachment: (args: { file: string }) => ({
+    file: args.file,
+    name: args.file,
+    size: 0,
+    mime: "application/octet-stream",
+  }),
+  open_attachment: () => {},
+  import_media: (args: { path: string }) => ({
+    type: "attachment",
+    file: `${crypto.randomUUID()}.bin`,
+    name: args.path.split(/[\\/]/).pop() ?? args.path,
+    size: 0,
+    mime: "application/octet-stream",
+  }),
+  import_media_bytes: () => ({ type: "image", file: "placeholder.png" }),
+  collect_media_garbage: () => ({
+    quarantined: 0,
+    quarantined_bytes: 0,
+    restored: 0,
+    purged: 0,
+    bytes_reclaimed: 0,
+  }),
+  get_storage_recovery: () => null,
+  get_settings: () => ({
+    backup_generations: 10,
+    storage_backend: "json",
+    data_dir: null,
+    workspace: "default",
+    encryption: null,
+    encryption_complete: false,
+    media_grace_days: 7,
+    max_image_bytes: 10 * 1024 * 1024,
+    max_attachment_bytes: 50 * 1024 * 1024,
+    trash_retention_days: 30,
+    history_interval_minutes: 10,
+    history_max_versions: 50,
+    history_max_bytes: 2 * 1024 * 1024,
+    daily_backups: 7,
+    weekly_backups: 4,
+  }),
+  set_storage_backend: (args: { backend: string }) => ({
+    backup_generations: 10,
+    storage_backend: args.backend,
+    data_dir: null,
+    workspace: "default",
+    encryption: null,
+    encryption_complete: false,
+    media_grace_days: 7,
+    max_image_bytes: 10 * 1024 * 1024,
+    max_attachment_bytes: 50 * 1024 * 1024,
+    trash_retention_days: 30,
+    history_interval_minutes: 10,
+    history_max_versions: 50,
+    history_max_bytes: 2 * 1024 * 1024,
+    daily_backups: 7,
+    weekly_backups: 4,
+  }),
+  list_backups: () => [],
+  restore_backup: () => {
+    throw new Error("Backups are not available in the browser preview");
+  },
+  export_archive: () => {
+    throw new Error("Archives are not available in the browser preview");
+  },
+  import_archive: () => {
+    throw new Error("Archives are not available in the browser preview");
+  },
+  get_storage_dir: () => ({ path: "localStorage", source: "default" }),
+  list_workspaces: () => ({ active: "default", workspaces: ["default"] }),
+  unlock_store: () => {},
+  lock_note: () => {
+    throw new Error("Note locks are not available in the browser preview");
+  },
 };
 
 /**
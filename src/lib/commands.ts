@@ -1,5 +1,23 @@
 import { invoke } from "@tauri-apps/api/core";
-import { Note, WindowLevel } from "../types";
+import {
+  ArchiveExport,
+  ArchiveImport,
+  ArchiveImportMode,
+  Attachment,
+  BackupInfo,
+  DataDirInfo,
+  ImportedMedia,
+  MediaGcReport,
+  Note,
+  NoteDiff,
+  NoteVersion,
+  RecoveryReport,
+  Settings,
+  StorageBackend,
+  VersionInfo,
+  WindowLevel,
+  WorkspaceList,
+} from "../types";
 
 export function createNote(): Promise<Note> {
   return invoke<Note>("create_note");
@@ -21,6 +39,51 @@ export function deleteNote(id: string): Promise<void> {
   return invoke("delete_note", { id });
 }
 
+export function listTrash(): Promise<Note[]> {
+  return invoke<Note[]>("list_trash");
+}
+
+export function restoreNote(id: string): Promise<Note> {
+  return invoke<Note>("restore_note", { id });
+}
+
+export function emptyTrash(): Promise<number> {
+  return invoke<number>("empty_trash");
+}
+
+export function lockNote(id: string, password: string): Promise<Note> {
+  return invoke<Note>("lock_note", { id, password });
+}
+
+export function unlockNote(id: string, password: string): Promise<Note> {
+  return invoke<Note>("unlock_note", { id, password });
+}
+
+export function relockNote(id: string): Promise<void> {
+  return invoke("relock_note", { id });
+}
+
+export function removeNoteLock(id: string, password: string): Promise<Note> {
+  return invoke<Note>("remove_note_lock", { id, password });
+}
+
+export function listNoteVersions(id: string): Promise<VersionInfo[]> {
+  return invoke<VersionInfo[]>("list_note_versions", { id });
+}
+
+export function getNoteVersion(id: string, versionId: string): Promise<NoteVersion> {
+  return invoke<NoteVersion>("get_note_version", { id, versionId });
+}
+
+/** Compares version `from` with version `to`, or with the note as it is now. */
+export function diffNoteVersions(id: string, from: string, to?: string): Promise<NoteDiff> {
+  return invoke<NoteDiff>("diff_note_versions", { id, from, to: to ?? null });
+}
+
+export function restoreNoteVersion(id: string, versionId: string): Promise<Note> {
+  return invoke<Note>("restore_note_version", { id, versionId });
+}
+
 export function updateNotePosition(
   id: string,
   x: number,
@@ -57,3 +120,105 @@ export function closeNoteWindow(id: string): Promise<void> {
 export function saveImage(data: string, mimeType: string): Promise<string> {
   return invoke<string>("save_image", { data, mimeType });
 }
+
+export function saveAttachment(data: string, fileName: string): Promise<Attachment> {
+  return invoke<Attachment>("save_attachment", { data, fileName });
+}
+
+/** Copies a file from disk into media without it passing through JS. */
+export function importMedia(path: string): Promise<ImportedMedia> {
+  return invoke<ImportedMedia>("import_media", { path });
+}
+
+/** Sends a file's bytes as a raw IPC body instead of base64. */
+export async function importMediaBytes(file: File): Promise<ImportedMedia> {
+  const bytes = new Uint8Array(await file.arrayBuffer());
+  return invoke<ImportedMedia>("import_media_bytes", bytes, {
+    headers: {
+      "x-file-name": encodeURIComponent(file.name),
+      "x-mime-type": file.type,
+    },
+  });
+}
+
+export function getAttachment(file: string): Promise<Attachment> {
+  return invoke<Attachment>("get_attachment", { file });
+}
+
+export function openAttachment(file: string): Promise<void> {
+  return invoke("open_attachment", { file });
+}
+
+export function collectMediaGarbage(): Promise<MediaGcReport> {
+  return invoke<MediaGcReport>("collect_media_garbage");
+}
+
+export function getStorageRecovery(): Promise<RecoveryReport | null> {
+  return invoke<RecoveryReport | null>("get_storage_recovery");
+}
+
+export function getSettings(): Promise<Settings> {
+  return invoke<Settings>("get_settings");
+}
+
+export function setStorageBackend(backend: StorageBackend): Promise<Settings> {
+  return invoke<Settings>("set_storage_backend", { backend });
+}
+
+export function getStorageDir(): Promise<DataDirInfo> {
+  return invoke<DataDirInfo>("get_storage_dir");
+}
+
+export function moveStorageDir(target: string): Promise<DataDirInfo> {
+  return invoke<DataDirInfo>("move_storage_dir", { target });
+}
+
+export function listWorkspaces(): Promise<WorkspaceList> {
+  return invoke<WorkspaceList>("list_workspaces");
+}
+
+export function createWorkspace(name: string): Promise<WorkspaceList> {
+  return invoke<WorkspaceList>("create_workspace", { name });
+}
+
+export function switchWorkspace(name: string): Promise<void> {
+  return invoke("switch_workspace", { name });
+}
+
+export function unlockStore(passphrase: string): Promise<void> {
+  return invoke("unlock_store", { passphrase });
+}
+
+export function enableEncryption(passphrase: string): Promise<Settings> {
+  return invoke<Settings>("enable_encryption", { passphrase });
+}
+
+export function disableEncryption(passphrase: string): Promise<Settings> {
+  return invoke<Settings>("disable_encryption", { passphrase });
+}
+
+export function listBackups(): Promise<BackupInfo[]> {
+  return invoke<BackupInfo[]>("list_backups");
+}
+
+/** Replaces every note with the backup's; the current notes are backed up first. */
+export function restoreBackup(id: string): Promise<void> {
+  return invoke("restore_backup", { id });
+}
+
+/**
+ * Writes the notes and their media to a zip picked in a save dialog.
+ * Resolves to null if the user cancelled.
+ */
+export function exportArchive(): Promise<ArchiveExport | null> {
+  return invoke<ArchiveExport | null>("export_archive");
+}
+
+/**
+ * Merges a zip picked in an open dialog into the notes, or replaces them
+ * after a backup. Without `mode` the user is asked. Resolves to null if
+ * the user cancelled.
+ */
+export function importArchive(mode?: ArchiveImportMode): Promise<ArchiveImport | null> {
+  return invoke<ArchiveImport | null>("import_archive", { mode: mode ?? null });
+}
@@ -11,7 +11,7 @@ import App from "./App";
 // If no noteId in URL and running in browser, inject one
 (async () => {
   const params = new URLSearchParams(window.location.search);
-  if (!params.get("noteId")) {
+  if (!params.get("noteId") && !params.get("unlock")) {
     const id = await ensureNoteId();
     params.set("noteId", id);
     window.history.replaceState({}, "", `?${params.toString()}`);
@@ -12,6 +12,207 @@ export interface Note {
   window_level: WindowLevel;
   created_at: string;
   updated_at: string;
+  lock?: NoteLock;
+  /** Set while the note is in the trash. */
+  deleted_at?: string;
+}
+
+/** Present on password-locked notes; `unlocked` is true once the password
+ * was entered this session and `content` holds the real text. */
+export interface NoteLock {
+  kdf: KdfParams;
+  content: string;
+  /** Media files the sealed content refers to, kept in the clear for GC. */
+  media: string[];
+  unlocked: boolean;
+}
+
+/** Payload of the `notes-changed` event. */
+export interface NotesChangedEvent {
+  kind: "created" | "updated" | "deleted";
+  id: string;
+  /** Set when notes.json was changed by another program. */
+  external: boolean;
+}
+
+export interface LostNote {
+  index: number;
+  id: string | null;
+  error: string;
+}
+
+export interface RecoveryReport {
+  corrupt_file: string;
+  error: string;
+  recovered: number;
+  lost: LostNote[];
+  truncated: boolean;
+}
+
+export type StorageBackend = "json" | "sqlite" | "markdown";
+
+export interface Settings {
+  backup_generations: number;
+  storage_backend: StorageBackend;
+  data_dir: string | null;
+  workspace: string;
+  encryption: KdfParams | null;
+  /** Set once every file is encrypted; plaintext is refused from then on. */
+  encryption_complete: boolean;
+  media_grace_days: number;
+  max_image_bytes: number;
+  max_attachment_bytes: number;
+  /** Days a deleted note stays in the trash; 0 keeps it until emptied. */
+  trash_retention_days: number;
+  /** Minutes between automatic snapshots in a note's history. */
+  history_interval_minutes: number;
+  history_max_versions: number;
+  history_max_bytes: number;
+  /** Daily and weekly backups kept in `backups/`; 0 turns them off. */
+  daily_backups: number;
+  weekly_backups: number;
+}
+
+export type BackupKind = "daily" | "weekly" | "pre_restore";
+
+export interface BackupInfo {
+  id: string;
+  kind: BackupKind;
+  created_at: string;
+  notes: number;
+  media: number;
+  /** Media files that were referenced but could not be found. */
+  missing_media: number;
+}
+
+export interface ArchiveExport {
+  path: string;
+  notes: number;
+  media: number;
+  /** Media files that were referenced but could not be found. */
+  missing_media: number;
+}
+
+export type ArchiveImportMode = "merge" | "replace";
+
+export interface ArchiveImport {
+  mode: ArchiveImportMode;
+  /** Notes added, or in replace mode the notes there are now. */
+  notes: number;
+  /** Archived notes already here unchanged, which were left out. */
+  skipped: number;
+  /** Archived notes given a new id because theirs was taken. */
+  renamed: number;
+  media: number;
+  /** Media files that were missing from the archive or were rejected. */
+  rejected_media: string[];
+}
+
+/** An earlier version of a note's content, as listed by `listNoteVersions`. */
+export interface VersionInfo {
+  id: string;
+  created_at: string;
+  size: number;
+}
+
+export interface NoteVersion {
+  id: string;
+  created_at: string;
+  content: string;
+}
+
+/** Plain-text comparison of two versions of a note. */
+export interface NoteDiff {
+  hunks: DiffHunk[];
+  insertions: number;
+  deletions: number;
+}
+
+export interface DiffHunk {
+  old_start: number;
+  old_len: number;
+  new_start: number;
+  new_len: number;
+  lines: DiffLine[];
+}
+
+export interface DiffLine {
+  kind: "equal" | "insert" | "delete";
+  old_line: number | null;
+  new_line: number | null;
+  /** `changed` marks the words that differ from the paired line. */
+  segments: { changed: boolean; text: string }[];
+}
+
+/** Argon2id parameters for encryption at rest; the key is never stored. */
+export interface KdfParams {
+  salt: string;
+  m_cost: number;
+  t_cost: number;
+  p_cost: number;
+  verifier: string;
+}
+
+export interface WorkspaceList {
+  active: string;
+  workspaces: string[];
+}
+
+export type DataDirSource = "cli" | "env" | "portable" | "settings" | "default";
+
+export type ImageFormat = "png" | "jpeg" | "gif" | "webp" | "svg";
+
+/** Rejection from `save_image`, tagged by `kind`. */
+export type ImageError =
+  | { kind: "invalid_base64"; message: string }
+  | { kind: "too_large"; size: number; max: number }
+  | { kind: "unsupported_type"; mime_type: string }
+  | { kind: "unrecognized_content" }
+  | { kind: "type_mismatch"; declared: ImageFormat; detected: ImageFormat }
+  | { kind: "invalid_svg"; message: string }
+  | { kind: "storage"; message: string };
+
+/** A non-image file stored in media, as returned by `save_attachment`. */
+export interface Attachment {
+  file: string;
+  name: string;
+  size: number;
+  mime: string;
+}
+
+/** Result of `import_media` / `import_media_bytes`. */
+export type ImportedMedia =
+  | { type: "image"; file: string }
+  | ({ type: "attachment" } & Attachment);
+
+export type AttachmentError =
+  | { kind: "invalid_base64"; message: string }
+  | { kind: "too_large"; size: number; max: number }
+  | { kind: "unsupported_type"; extension: string }
+  | { kind: "content_mismatch"; extension: string }
+  | { kind: "storage"; message: string };
+
+/**
+ * Rejection from `import_media` / `import_media_bytes`: an image or
+ * attachment error, or a request turned down before the file was read.
+ */
+export type ImportError =
+  | ImageError
+  | AttachmentError
+  | { kind: "not_dropped"; path: string }
+  | { kind: "invalid_body" };
+
+export interface MediaGcReport {
+  quarantined: number;
+  quarantined_bytes: number;
+  restored: number;
+  purged: number;
+  bytes_reclaimed: number;
+}
+
+export interface DataDirInfo {
+  path: string;
+  source: DataDirSource;
 }
 
 export interface Theme {